@@ -207,4 +207,110 @@ pub fn determinant<T: Float + Debug + Send + Sync + 'static + Sum>(
 
         Err(res) => Err(res),
     }
-}
\ No newline at end of file
+}
+
+/// Computes the Cholesky factorization of a symmetric positive definite matrix
+///
+/// Returns the lower triangular matrix L such that A = L * L^T
+pub fn cholesky_decomposition<T: Float + Debug + Send + Sync + 'static + Sum>(
+    to_decompose: &Matrix<T>,
+) -> Result<Matrix<T>, String> {
+    if to_decompose.rows != to_decompose.columns {
+        return Err(format!(
+            "Cholesky: the matrix is not squared ({}, {})",
+            to_decompose.rows, to_decompose.columns
+        ));
+    }
+
+    let dim = to_decompose.rows;
+    let mut lower: Matrix<T> = Matrix::zeroes(dim, dim);
+
+    for row in 0..dim {
+        for col in 0..=row {
+            let sum: T = (0..col).fold(T::zero(), |acc, it| acc + lower[row][it] * lower[col][it]);
+
+            if row == col {
+                let pivot = to_decompose[row][row] - sum;
+
+                if !pivot.is_finite() || pivot <= T::zero() {
+                    return Err(format!(
+                        "Cholesky: the matrix is not positive definite (pivot {row} is {pivot:?})"
+                    ));
+                }
+
+                lower[row][col] = pivot.sqrt();
+            } else {
+                lower[row][col] = (to_decompose[row][col] - sum) / lower[col][col];
+            }
+        }
+    }
+
+    Ok(lower)
+}
+
+/// Solves L * x = b for a lower triangular matrix L
+pub fn forward_substitution<T: Float + 'static>(
+    lower: &Matrix<T>,
+    b: &[T],
+) -> Result<Vec<T>, &'static str> {
+    if lower.rows != b.len() {
+        return Err("The vector size do not match");
+    }
+
+    let mut solution: Vec<T> = Vec::with_capacity(b.len());
+
+    for row in 0..lower.rows {
+        let sum: T = (0..row).fold(T::zero(), |acc, it| acc + lower[row][it] * solution[it]);
+        solution.push((b[row] - sum) / lower[row][row]);
+    }
+
+    Ok(solution)
+}
+
+/// Computes the log determinant from a Cholesky factor
+pub fn cholesky_log_determinant<T: Float + 'static>(lower: &Matrix<T>) -> T {
+    let two: T = T::from(2.0).unwrap();
+
+    (0..lower.rows).fold(T::zero(), |acc, idx| acc + two * lower[idx][idx].ln())
+}
+
+/// Solves L^T * x = b for a lower triangular matrix L
+pub fn backward_substitution<T: Float + 'static>(
+    lower: &Matrix<T>,
+    b: &[T],
+) -> Result<Vec<T>, &'static str> {
+    if lower.rows != b.len() {
+        return Err("The vector size do not match");
+    }
+
+    let mut solution: Vec<T> = vec![T::zero(); b.len()];
+
+    for row in (0..lower.rows).rev() {
+        let sum: T =
+            (row + 1..lower.rows).fold(T::zero(), |acc, it| acc + lower[it][row] * solution[it]);
+        solution[row] = (b[row] - sum) / lower[row][row];
+    }
+
+    Ok(solution)
+}
+
+/// Inverses a symmetric positive definite matrix through its Cholesky factorization
+pub fn cholesky_inverse<T: Float + Debug + Send + Sync + 'static + Sum>(
+    to_inverse: &Matrix<T>,
+) -> Result<Matrix<T>, String> {
+    let lower = cholesky_decomposition(to_inverse)?;
+    let dim = lower.rows;
+    let mut inverse: Matrix<T> = Matrix::zeroes(dim, dim);
+
+    for col in 0..dim {
+        let mut unit = vec![T::zero(); dim];
+        unit[col] = T::one();
+
+        let solved = forward_substitution(&lower, &unit)?;
+        let solved = backward_substitution(&lower, &solved)?;
+
+        (0..dim).for_each(|row| inverse[row][col] = solved[row]);
+    }
+
+    Ok(inverse)
+}
@@ -10,6 +10,7 @@ use crate::calculations::linear_algebra::dot_product;
 ///
 /// This data structure is a key component of the project as 
 /// most of the operations are performed on it. 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Matrix<T> {
     pub content: Vec<T>,
     pub rows: usize,
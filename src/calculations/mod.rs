@@ -1,4 +1,5 @@
 pub mod linear_algebra;
 pub mod matrix;
 pub mod matrix_traits;
+pub mod partition;
 pub mod stats;
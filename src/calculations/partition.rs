@@ -0,0 +1,203 @@
+//! Hard assignment of rows to clusters
+//!
+//! A Partition keeps the cluster of every row and the size of every cluster,
+//! instead of one full-length 0/1 vector per cluster.
+
+use std::fmt::Debug;
+use std::iter::Sum;
+
+use num::Float;
+
+use super::matrix::Matrix;
+
+/// Cluster of every row and number of rows in every cluster
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "SavedPartition", into = "SavedPartition")
+)]
+pub struct Partition {
+    /// Cluster of each row
+    labels: Vec<usize>,
+    /// Number of rows in each cluster
+    sizes: Vec<usize>,
+    /// Rows of each cluster, built along with the sizes
+    members: Vec<Vec<usize>>,
+}
+
+/// Saved form of a partition, the rows of each cluster are built again on loading
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedPartition {
+    labels: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Partition> for SavedPartition {
+    fn from(partition: Partition) -> Self {
+        SavedPartition {
+            labels: partition.labels,
+            sizes: partition.sizes,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SavedPartition> for Partition {
+    type Error = &'static str;
+
+    fn try_from(saved: SavedPartition) -> Result<Self, Self::Error> {
+        let partition = Partition::new(saved.labels, saved.sizes.len())?;
+
+        if partition.sizes != saved.sizes {
+            return Err("The cluster sizes do not match the labels");
+        }
+
+        Ok(partition)
+    }
+}
+
+impl Partition {
+    /// Builds a partition with `k` clusters from the cluster of each row
+    pub fn new(labels: Vec<usize>, k: usize) -> Result<Partition, &'static str> {
+        let mut sizes: Vec<usize> = vec![0; k];
+        let mut members: Vec<Vec<usize>> = vec![Vec::new(); k];
+
+        for (row, label) in labels.iter().enumerate() {
+            if *label >= k {
+                return Err("A label is larger than the number of clusters");
+            }
+            sizes[*label] += 1;
+            members[*label].push(row);
+        }
+
+        Ok(Partition {
+            labels,
+            sizes,
+            members,
+        })
+    }
+
+    /// Partition putting every row in a single cluster
+    pub fn single(rows: usize) -> Partition {
+        Partition {
+            labels: vec![0; rows],
+            sizes: vec![rows],
+            members: vec![(0..rows).collect()],
+        }
+    }
+
+    /// Returns the cluster of each row
+    pub fn labels(&self) -> &[usize] {
+        &self.labels
+    }
+
+    /// Returns the number of rows in each cluster
+    pub fn sizes(&self) -> &[usize] {
+        &self.sizes
+    }
+
+    /// Returns the number of clusters
+    pub fn k(&self) -> usize {
+        self.sizes.len()
+    }
+
+    /// Returns the number of rows
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// Returns whether there is no row
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Returns the rows of a cluster
+    pub fn members(&self, cluster: usize) -> impl Iterator<Item = usize> + '_ {
+        self.members[cluster].iter().copied()
+    }
+
+    /// Returns the total weight of the rows of each cluster
+    pub fn weighted_sizes<T: Float>(&self, weights: &[T]) -> Vec<T> {
+        let mut sizes: Vec<T> = vec![T::zero(); self.k()];
+        self.labels
+            .iter()
+            .zip(weights)
+            .for_each(|(label, weight)| sizes[*label] = sizes[*label] + *weight);
+
+        sizes
+    }
+
+    /// Builds a partition from one 0/1 vector per cluster
+    ///
+    /// Every row needs exactly one 1 among the clusters.
+    pub fn from_one_hot(zs: &[Vec<u8>]) -> Result<Partition, &'static str> {
+        let rows = zs.first().map_or(0, |z| z.len());
+
+        if zs.iter().any(|z| z.len() != rows) {
+            return Err("The clusters do not have the same number of rows");
+        }
+
+        let labels = (0..rows)
+            .map(|row| {
+                let mut clusters = (0..zs.len()).filter(|cluster| zs[*cluster][row] == 1);
+                match (clusters.next(), clusters.next()) {
+                    (Some(cluster), None) => Ok(cluster),
+                    _ => Err("Every row has to be in exactly one cluster"),
+                }
+            })
+            .collect::<Result<Vec<usize>, &'static str>>()?;
+
+        Partition::new(labels, zs.len())
+    }
+
+    /// Returns one 0/1 vector per cluster
+    pub fn to_one_hot(&self) -> Vec<Vec<u8>> {
+        (0..self.k())
+            .map(|cluster| {
+                self.labels
+                    .iter()
+                    .map(|label| u8::from(*label == cluster))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Builds a partition from the probability of each cluster (rows) for each data point (columns)
+    ///
+    /// Every point goes to its most likely cluster.
+    pub fn from_responsibilities<T>(responsibilities: &Matrix<T>) -> Partition
+    where
+        T: Float + Debug + Send + Sync + Sum + 'static,
+    {
+        let labels = (0..responsibilities.columns)
+            .map(|col| {
+                (0..responsibilities.rows).fold(0, |best, cluster| {
+                    if responsibilities[cluster][col] > responsibilities[best][col] {
+                        cluster
+                    } else {
+                        best
+                    }
+                })
+            })
+            .collect();
+
+        Partition::new(labels, responsibilities.rows).expect("Labels are within the clusters")
+    }
+
+    /// Returns the probability of each cluster (rows) for each data point (columns)
+    pub fn to_responsibilities<T>(&self) -> Matrix<T>
+    where
+        T: Float + Debug + Send + Sync + Sum + 'static,
+    {
+        let mut responsibilities: Matrix<T> = Matrix::zeroes(self.k(), self.len());
+        self.labels
+            .iter()
+            .enumerate()
+            .for_each(|(row, label)| responsibilities[*label][row] = T::one());
+
+        responsibilities
+    }
+}
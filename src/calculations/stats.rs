@@ -1,38 +1,83 @@
 use std::fmt::Debug;
+use std::iter::zip;
 use std::{iter::Sum, ops::Mul};
 
 use num::Float;
 
 use crate::calculations::linear_algebra::dot_product;
 
-use super::linear_algebra::determinant;
+use super::linear_algebra::{
+    cholesky_decomposition, cholesky_log_determinant, determinant, forward_substitution,
+};
+use super::partition::Partition;
 use super::{linear_algebra::slow_inverse_matrix, matrix::Matrix};
 
-/// Computes the covariance
-pub fn covariance<T: Float>(a: &Matrix<T>, zs: &Vec<u8>) -> Matrix<T>
+/// Meaning of the row weights, which sets the unbiased covariance denominator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WeightKind {
+    /// Each row stands for as many identical records as its weight
+    Frequency,
+    /// The weights give the relative importance of the rows, such as survey weights
+    Reliability,
+}
+
+/// Computes the covariance of the rows of a cluster
+pub fn covariance<T: Float>(a: &Matrix<T>, partition: &Partition, cluster: usize) -> Matrix<T>
 where
     T: Mul + Sum + Debug + Send + Sync + 'static,
+{
+    weighted_covariance(
+        a,
+        partition,
+        cluster,
+        &vec![T::one(); a.rows],
+        WeightKind::Frequency,
+    )
+}
+
+/// Computes the covariance of the rows of a cluster with a weight for each row
+///
+/// With frequency weights, integer weights give the same result as repeating
+/// the rows. Reliability weights only matter relative to each other, so that
+/// normalised weights can be used.
+pub fn weighted_covariance<T>(
+    a: &Matrix<T>,
+    partition: &Partition,
+    cluster: usize,
+    weights: &[T],
+    kind: WeightKind,
+) -> Matrix<T>
+where
+    T: Float + Mul + Sum + Debug + Send + Sync + 'static,
 {
     let mut cov_ma: Matrix<T> = Matrix::zeroes(a.columns, a.columns);
-    let means = means_zs(a, zs).unwrap();
+    let means = weighted_means_zs(a, partition, cluster, weights).unwrap();
 
-    let number_attributes: u32 = zs.iter().fold(0u32, |acc, i| acc + *i as u32);
-    let number_attributes = T::from(number_attributes).unwrap();
+    let rows: Vec<usize> = partition.members(cluster).collect();
+    let total: T = rows.iter().fold(T::zero(), |acc, row| acc + weights[*row]);
+    let denominator: T = match kind {
+        WeightKind::Frequency => total - T::one(),
+        WeightKind::Reliability => {
+            let squares = rows
+                .iter()
+                .fold(T::zero(), |acc, row| acc + weights[*row] * weights[*row]);
+            total - squares / total
+        }
+    };
 
-    (0..a[0].len()).for_each(|col1| {
-        (0..a[0].len()).for_each(|col2| {
-            let sum: T = (0..a.len())
+    (0..a.columns).for_each(|col1| {
+        (col1..a.columns).for_each(|col2| {
+            let sum: T = rows
+                .iter()
                 .map(|row| {
-                    if zs[row] == 1 {
-                        (a[row][col1] - means[col1]).mul(a[row][col2] - means[col2])
-                    } else {
-                        T::from(0.0).unwrap()
-                    }
+                    weights[*row]
+                        .mul((a[*row][col1] - means[col1]).mul(a[*row][col2] - means[col2]))
                 })
                 .sum();
 
-            cov_ma[col1][col2] = sum / (number_attributes - T::from(1.0).unwrap());
-            cov_ma[col2][col1] = sum / (number_attributes - T::from(1.0).unwrap());
+            cov_ma[col1][col2] = sum / denominator;
+            cov_ma[col2][col1] = sum / denominator;
         })
     });
 
@@ -62,30 +107,47 @@ where
     cov_ma
 }
 
-/// Computes the column wise means by taking the z values into account
-pub fn means_zs<T: Float>(a: &Matrix<T>, zs: &Vec<u8>) -> Result<Vec<T>, &'static str>
+/// Computes the column wise means of the rows of a cluster
+pub fn means_zs<T: Float>(
+    a: &Matrix<T>,
+    partition: &Partition,
+    cluster: usize,
+) -> Result<Vec<T>, &'static str>
 where
     T: Send + Sync + Debug + Sum + 'static,
 {
-    let number_attributes = zs.iter().fold(0u32, |acc, i| acc + *i as u32);
-    let number_attributes = T::from(number_attributes).unwrap();
-
-    let means = (0..a[0].len())
-        .map(|attr| {
-            let col: T = (0..a.len())
-                .zip(zs)
-                .map(|(row, z)| match z {
-                    1 => a[row][attr],
-                    0 => T::zero(),
-                    _ => panic!("z can only be 0 or 1"),
-                })
-                .sum();
+    weighted_means_zs(a, partition, cluster, &vec![T::one(); a.rows])
+}
 
-            col / number_attributes
-        })
-        .collect();
+/// Computes the column wise means of the rows of a cluster, taking the row weights into account
+pub fn weighted_means_zs<T>(
+    a: &Matrix<T>,
+    partition: &Partition,
+    cluster: usize,
+    weights: &[T],
+) -> Result<Vec<T>, &'static str>
+where
+    T: Float + Send + Sync + Debug + Sum + 'static,
+{
+    if weights.len() != a.rows {
+        return Err("The weights do not match the number of rows");
+    }
+
+    if partition.len() != a.rows {
+        return Err("The partition does not match the number of rows");
+    }
+
+    let mut number_attributes: T = T::zero();
+    let mut sums: Vec<T> = vec![T::zero(); a.columns];
 
-    Ok(means)
+    partition.members(cluster).for_each(|row| {
+        number_attributes = number_attributes + weights[row];
+        sums.iter_mut()
+            .zip(&a[row])
+            .for_each(|(sum, val)| *sum = *sum + *val * weights[row]);
+    });
+
+    Ok(sums.iter().map(|sum| *sum / number_attributes).collect())
 }
 
 /// Computes the likelihood the given gaussian generated the data
@@ -136,3 +198,208 @@ where
 
     Ok(likelihoods)
 }
+
+/// Computes the squared Mahalanobis distance of every row to the means
+///
+/// Also returns the log determinant of the covariance, both come from its
+/// Cholesky factorization. Returns an error if the covariance is not positive definite.
+pub fn mahalanobis_distances<T>(
+    data: &Matrix<T>,
+    covariance: &Matrix<T>,
+    means: &[T],
+) -> Result<(Vec<T>, T), String>
+where
+    T: Float + Send + Sync + Debug + Sum + 'static,
+{
+    if covariance.rows != data.columns || means.len() != data.columns {
+        return Err(format!(
+            "The data has {} columns but the covariance is ({}, {}) and the means have {} entries",
+            data.columns,
+            covariance.rows,
+            covariance.columns,
+            means.len()
+        ));
+    }
+
+    let lower = cholesky_decomposition(covariance)?;
+
+    let distances: Vec<T> = (0..data.rows)
+        .map(|row| {
+            let centered: Vec<T> = zip(&data[row], means).map(|(x, m)| *x - *m).collect();
+            let solved = forward_substitution(&lower, &centered).unwrap();
+            dot_product(&solved, &solved).unwrap()
+        })
+        .collect();
+
+    Ok((distances, cholesky_log_determinant(&lower)))
+}
+
+/// Computes the log density of the data under the given gaussian
+///
+/// Relies on the Cholesky factorization of the covariance instead of its inverse.
+/// Returns an error if the covariance is not positive definite.
+pub fn log_multivariate_gaussian<T>(
+    data: &Matrix<T>,
+    covariance: &Matrix<T>,
+    means: &[T],
+) -> Result<Vec<T>, String>
+where
+    T: Float + Send + Sync + Debug + Sum + 'static,
+{
+    let (distances, log_determinant) = mahalanobis_distances(data, covariance, means)?;
+
+    let two: T = T::from(2.0).unwrap();
+    let log_two_pi: T = (two * T::from(std::f64::consts::PI).unwrap()).ln();
+    let constant: T = T::from(data.columns).unwrap() * log_two_pi + log_determinant;
+
+    Ok(distances
+        .iter()
+        .map(|mahalanobis| -(constant + *mahalanobis) / two)
+        .collect())
+}
+
+/// Turns the log weighted densities (components x rows) into responsibilities
+///
+/// Every column is normalized in place with the log-sum-exp trick and the
+/// log likelihood of the data is returned.
+pub fn normalize_log_densities<T>(log_densities: &mut Matrix<T>) -> T
+where
+    T: Float + Send + Sync + Debug + Sum + 'static,
+{
+    let weights = vec![T::one(); log_densities.columns];
+    weighted_normalize_log_densities(log_densities, &weights)
+}
+
+/// Same as normalize_log_densities, each row counting as many times as its weight
+/// in the returned log likelihood
+pub fn weighted_normalize_log_densities<T>(log_densities: &mut Matrix<T>, weights: &[T]) -> T
+where
+    T: Float + Send + Sync + Debug + Sum + 'static,
+{
+    let components = log_densities.rows;
+    let mut log_likelihood: T = T::zero();
+
+    for idx in 0..log_densities.columns {
+        let max = (0..components).fold(T::neg_infinity(), |acc, i| acc.max(log_densities[i][idx]));
+        let sum = (0..components).fold(T::zero(), |acc, i| {
+            acc + (log_densities[i][idx] - max).exp()
+        });
+        let log_sum = max + sum.ln();
+
+        (0..components)
+            .for_each(|i| log_densities[i][idx] = (log_densities[i][idx] - log_sum).exp());
+
+        // Rows without weight do not count, even when their density underflows
+        if weights[idx] != T::zero() {
+            log_likelihood = log_likelihood + weights[idx] * log_sum;
+        }
+    }
+
+    log_likelihood
+}
+
+/// Computes the log density of the data under the given multivariate Student t
+pub fn log_multivariate_t<T>(
+    data: &Matrix<T>,
+    covariance: &Matrix<T>,
+    means: &[T],
+    degrees_of_freedom: T,
+) -> Result<Vec<T>, String>
+where
+    T: Float + Send + Sync + Debug + Sum + 'static,
+{
+    let (distances, log_determinant) = mahalanobis_distances(data, covariance, means)?;
+
+    let two: T = T::from(2.0).unwrap();
+    let p: T = T::from(data.columns).unwrap();
+    let pi: T = T::from(std::f64::consts::PI).unwrap();
+    let half_total: T = (degrees_of_freedom + p) / two;
+
+    let constant: T = ln_gamma(half_total)
+        - ln_gamma(degrees_of_freedom / two)
+        - p / two * (degrees_of_freedom * pi).ln()
+        - log_determinant / two;
+
+    Ok(distances
+        .iter()
+        .map(|mahalanobis| {
+            constant - half_total * (T::one() + *mahalanobis / degrees_of_freedom).ln()
+        })
+        .collect())
+}
+
+/// Computes the logarithm of the gamma function using the Lanczos approximation
+pub fn ln_gamma<T: Float>(x: T) -> T {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    let pi: T = T::from(std::f64::consts::PI).unwrap();
+    let half: T = T::from(0.5).unwrap();
+
+    // Reflection formula for the small values
+    if x < half {
+        return (pi / (pi * x).sin()).ln() - ln_gamma(T::one() - x);
+    }
+
+    let x = x - T::one();
+    let t = x + T::from(7.5).unwrap();
+    let series = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(T::from(COEFFICIENTS[0]).unwrap(), |acc, (idx, c)| {
+            acc + T::from(*c).unwrap() / (x + T::from(idx + 1).unwrap())
+        });
+
+    half * (T::from(2.0).unwrap() * pi).ln() + (x + half) * t.ln() - t + series.ln()
+}
+
+/// Computes the digamma function, the derivative of ln_gamma
+pub fn digamma<T: Float>(x: T) -> T {
+    let mut x = x;
+    let mut result = T::zero();
+
+    // Recurrence until the asymptotic expansion is accurate
+    while x < T::from(6.0).unwrap() {
+        result = result - T::one() / x;
+        x = x + T::one();
+    }
+
+    let inv = T::one() / x;
+    let inv2 = inv * inv;
+    let series = inv2
+        * (T::from(1.0 / 12.0).unwrap()
+            - inv2
+                * (T::from(1.0 / 120.0).unwrap()
+                    - inv2
+                        * (T::from(1.0 / 252.0).unwrap()
+                            - inv2
+                                * (T::from(1.0 / 240.0).unwrap()
+                                    - inv2 * T::from(1.0 / 132.0).unwrap()))));
+
+    result + x.ln() - inv / T::from(2.0).unwrap() - series
+}
+
+/// Computes the volume of the box bounding the data
+///
+/// Used as the support of a uniform noise component.
+pub fn hypervolume<T>(data: &Matrix<T>) -> T
+where
+    T: Float + Send + Sync + Debug + Sum + 'static,
+{
+    (0..data.columns).fold(T::one(), |volume, col| {
+        let (min, max) = (0..data.rows).fold((T::infinity(), T::neg_infinity()), |acc, row| {
+            (acc.0.min(data[row][col]), acc.1.max(data[row][col]))
+        });
+
+        volume * (max - min)
+    })
+}
@@ -0,0 +1,431 @@
+//! Bootstrap inference for the GaussianMixtureModel
+//!
+//! The model is refitted on bootstrap replicates of the data. For the standard
+//! errors, the components of each refit are matched to the ones of the original
+//! fit through their means, so that label switching does not mix the parameters
+//! of different components. The likelihood ratio test on the number of components
+//! refits both models on data drawn from the smaller one.
+
+use std::fmt::Debug;
+use std::iter::Sum;
+use std::ops::{Add, Div, Mul, Sub};
+use std::str::FromStr;
+
+use itertools::Itertools;
+use num::Float;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+use super::distances::Distance;
+use super::gmm::{CovarianceModel, DegeneratePolicy, GaussianMixtureModel};
+
+use crate::calculations::matrix::Matrix;
+
+/// Above this number of components, the matching falls back to a greedy search
+const EXHAUSTIVE_MATCHING: usize = 7;
+
+/// Way the bootstrap replicates are built
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BootstrapKind {
+    /// Rows drawn with replacement from the data
+    Nonparametric,
+    /// Rows drawn from the fitted model
+    Parametric,
+    /// Every row kept with a random weight, drawn from a flat Dirichlet
+    WeightedLikelihood,
+}
+
+/// Standard errors and percentile intervals of a group of parameters
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParameterUncertainty<T> {
+    pub standard_errors: Matrix<T>,
+    pub lower: Matrix<T>,
+    pub upper: Matrix<T>,
+}
+
+/// Result of the bootstrap
+///
+/// The parameters have one row per component, the mixtures being a single column
+/// and the variances the diagonal of the covariance matrices.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bootstrap<T> {
+    pub kind: BootstrapKind,
+    /// Replicates whose fit succeeded, the others are left out
+    pub replicates: usize,
+    pub failed: usize,
+    /// Coverage of the percentile intervals
+    pub level: T,
+    pub mixtures: ParameterUncertainty<T>,
+    pub means: ParameterUncertainty<T>,
+    pub variances: ParameterUncertainty<T>,
+}
+
+/// Result of the sequential bootstrap likelihood ratio test
+///
+/// Entry g tests `components[g]` against `components[g] + 1` components.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LikelihoodRatioTest<T> {
+    pub components: Vec<usize>,
+    /// Observed statistic, -2 * (loglik(G) - loglik(G + 1))
+    pub statistics: Vec<T>,
+    pub p_values: Vec<T>,
+    /// Statistic of every replicate whose fits succeeded
+    pub bootstrap_statistics: Vec<Vec<T>>,
+}
+
+/// Parameters of one refit, in the component order of the original fit
+struct Replicate<T> {
+    mixtures: Vec<T>,
+    means: Vec<Vec<T>>,
+    variances: Vec<Vec<T>>,
+}
+
+/// Bootstraps the parameters of a fitted GaussianMixtureModel
+///
+/// Every replicate is refitted with the settings of the model, in parallel.
+pub fn bootstrap<T>(
+    gmm: &GaussianMixtureModel<T>,
+    data: &Matrix<T>,
+    kind: BootstrapKind,
+    replicates: usize,
+    level: T,
+    distance: &dyn Distance<T>,
+    seed: u64,
+) -> Result<Bootstrap<T>, &'static str>
+where
+    T: Float
+        + Div
+        + Mul
+        + Add
+        + Sub<Output = T>
+        + Sum<<T as Mul>::Output>
+        + Copy
+        + Debug
+        + Sync
+        + Send
+        + PartialOrd
+        + FromStr
+        + 'static,
+    for<'a> &'a T: Sub<&'a T, Output = T>,
+    Vec<T>: FromIterator<<T as Div>::Output>,
+{
+    if gmm.means().is_empty() {
+        return Err("The model has not been fitted");
+    }
+
+    if gmm.noise.is_some() {
+        return Err("The bootstrap does not support the noise component");
+    }
+
+    if replicates < 2 {
+        return Err("At least two replicates are needed");
+    }
+
+    if level <= T::zero() || level >= T::one() {
+        return Err("The level has to be between 0 and 1");
+    }
+
+    let k = gmm.number_components();
+
+    let fitted: Vec<Option<Replicate<T>>> = (0..replicates)
+        .into_par_iter()
+        .map(|replicate| {
+            let replicate_seed = seed.wrapping_add(replicate as u64);
+            let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(replicate_seed);
+
+            let (rows, weights): (Matrix<T>, Option<Vec<T>>) = match kind {
+                BootstrapKind::Nonparametric => {
+                    let mut rows: Matrix<T> = Matrix::empty();
+                    (0..data.rows).for_each(|_| {
+                        rows.append_vector(&data[rng.gen_range(0..data.rows)], 0)
+                            .expect("Unable to append row");
+                    });
+                    (rows, None)
+                }
+                BootstrapKind::Parametric => {
+                    let (rows, _) = gmm.sample(data.rows, replicate_seed).ok()?;
+                    (rows, None)
+                }
+                BootstrapKind::WeightedLikelihood => {
+                    // Normalised exponential draws follow a flat Dirichlet
+                    let draws: Vec<T> = (0..data.rows)
+                        .map(|_| T::from(-(1.0 - rng.gen::<f64>()).ln()).unwrap())
+                        .collect();
+                    let total = draws.iter().fold(T::zero(), |acc, d| acc + *d);
+                    let n = T::from(data.rows).unwrap();
+                    (
+                        data.clone(),
+                        Some(draws.iter().map(|d| *d * n / total).collect()),
+                    )
+                }
+            };
+
+            let mut refit = gmm.new_like(replicate_seed);
+            refit.fit(&rows, distance, None, weights.as_deref()).ok()?;
+
+            if refit.number_components() != k {
+                return None;
+            }
+
+            Some(align(gmm, &refit))
+        })
+        .collect();
+
+    let successful: Vec<Replicate<T>> = fitted.into_iter().flatten().collect();
+    let failed = replicates - successful.len();
+
+    if successful.len() < 2 {
+        return Err("Fewer than two replicates could be fitted");
+    }
+
+    let p = gmm.means().columns;
+
+    Ok(Bootstrap {
+        kind,
+        replicates: successful.len(),
+        failed,
+        level,
+        mixtures: summarise(&successful, k, 1, level, |r, i, _| r.mixtures[i]),
+        means: summarise(&successful, k, p, level, |r, i, j| r.means[i][j]),
+        variances: summarise(&successful, k, p, level, |r, i, j| r.variances[i][j]),
+    })
+}
+
+/// Reorders the components of the refit to match the original fit
+///
+/// The permutation minimises the total squared distance between the means.
+fn align<T>(reference: &GaussianMixtureModel<T>, refit: &GaussianMixtureModel<T>) -> Replicate<T>
+where
+    T: Float
+        + Div
+        + Mul
+        + Add
+        + Sub<Output = T>
+        + Sum<<T as Mul>::Output>
+        + Copy
+        + Debug
+        + Sync
+        + Send
+        + PartialOrd
+        + FromStr
+        + 'static,
+    for<'a> &'a T: Sub<&'a T, Output = T>,
+    Vec<T>: FromIterator<<T as Div>::Output>,
+{
+    let k = reference.number_components();
+    let cost = |i: usize, j: usize| {
+        reference.means()[i]
+            .iter()
+            .zip(&refit.means()[j])
+            .fold(T::zero(), |acc, (a, b)| acc + (*a - *b) * (*a - *b))
+    };
+
+    // order[i] is the component of the refit matched to the component i of the reference
+    let order: Vec<usize> = if k <= EXHAUSTIVE_MATCHING {
+        (0..k)
+            .permutations(k)
+            .map(|order| {
+                let total = order
+                    .iter()
+                    .enumerate()
+                    .fold(T::zero(), |acc, (i, j)| acc + cost(i, *j));
+                (order, total)
+            })
+            .fold((Vec::new(), T::infinity()), |best, current| {
+                if current.1 < best.1 {
+                    current
+                } else {
+                    best
+                }
+            })
+            .0
+    } else {
+        let mut taken = vec![false; k];
+        (0..k)
+            .map(|i| {
+                let j = (0..k)
+                    .filter(|j| !taken[*j])
+                    .fold(None, |best: Option<usize>, j| match best {
+                        Some(b) if cost(i, b) <= cost(i, j) => Some(b),
+                        _ => Some(j),
+                    })
+                    .unwrap();
+                taken[j] = true;
+                j
+            })
+            .collect()
+    };
+
+    Replicate {
+        mixtures: order.iter().map(|j| refit.mixtures()[*j]).collect(),
+        means: order.iter().map(|j| refit.means()[*j].to_vec()).collect(),
+        variances: order
+            .iter()
+            .map(|j| {
+                let cov = &refit.covariance_matrices()[*j];
+                (0..cov.rows).map(|d| cov[d][d]).collect()
+            })
+            .collect(),
+    }
+}
+
+/// Computes the standard errors and percentile intervals of a (rows x columns) parameter
+fn summarise<T, F>(
+    replicates: &[Replicate<T>],
+    rows: usize,
+    columns: usize,
+    level: T,
+    value: F,
+) -> ParameterUncertainty<T>
+where
+    T: Float + Debug + Send + Sync + Sum + 'static,
+    F: Fn(&Replicate<T>, usize, usize) -> T,
+{
+    let mut standard_errors: Matrix<T> = Matrix::zeroes(rows, columns);
+    let mut lower: Matrix<T> = Matrix::zeroes(rows, columns);
+    let mut upper: Matrix<T> = Matrix::zeroes(rows, columns);
+    let n = T::from(replicates.len()).unwrap();
+    let alpha = (T::one() - level) / T::from(2.0).unwrap();
+
+    for i in 0..rows {
+        for j in 0..columns {
+            let mut values: Vec<T> = replicates.iter().map(|r| value(r, i, j)).collect();
+            let mean = values.iter().fold(T::zero(), |acc, v| acc + *v) / n;
+            let variance = values
+                .iter()
+                .fold(T::zero(), |acc, v| acc + (*v - mean) * (*v - mean))
+                / (n - T::one());
+
+            values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+
+            standard_errors[i][j] = variance.sqrt();
+            lower[i][j] = quantile(&values, alpha);
+            upper[i][j] = quantile(&values, T::one() - alpha);
+        }
+    }
+
+    ParameterUncertainty {
+        standard_errors,
+        lower,
+        upper,
+    }
+}
+
+/// Linearly interpolated quantile of sorted values
+fn quantile<T: Float>(sorted: &[T], probability: T) -> T {
+    let position = probability * T::from(sorted.len() - 1).unwrap();
+    let below = position.floor().to_usize().unwrap();
+    let above = position.ceil().to_usize().unwrap();
+    let fraction = position - position.floor();
+
+    sorted[below] + (sorted[above] - sorted[below]) * fraction
+}
+
+/// Sequential bootstrap likelihood ratio test of G against G + 1 components
+///
+/// Mirrors `mclustBootstrapLRT`: for G = 1, 2, ... both models are fitted to the
+/// data, then refitted on `replicates` samples drawn from the G components fit.
+/// The test stops at the first G whose p-value is above `significance`, or at
+/// `max_components`. Degenerate components are reseeded, replicates whose fits
+/// still fail are left out of the p-value.
+#[allow(clippy::too_many_arguments)]
+pub fn bootstrap_lrt<T>(
+    data: &Matrix<T>,
+    max_components: usize,
+    model: CovarianceModel,
+    replicates: usize,
+    significance: T,
+    seed: u64,
+    max_steps: i32,
+    tolerance: T,
+    distance: &dyn Distance<T>,
+) -> Result<LikelihoodRatioTest<T>, &'static str>
+where
+    T: Float
+        + Div
+        + Mul
+        + Add
+        + Sub<Output = T>
+        + Sum<<T as Mul>::Output>
+        + Copy
+        + Debug
+        + Sync
+        + Send
+        + PartialOrd
+        + FromStr
+        + 'static,
+    for<'a> &'a T: Sub<&'a T, Output = T>,
+    Vec<T>: FromIterator<<T as Div>::Output>,
+{
+    if max_components < 1 {
+        return Err("At least one component has to be tested");
+    }
+
+    if replicates < 1 {
+        return Err("At least one replicate is needed");
+    }
+
+    let fit = |rows: &Matrix<T>, k: usize| {
+        let mut gmm = GaussianMixtureModel::new(
+            k,
+            seed,
+            vec![T::one() / T::from(k).unwrap(); k],
+            max_steps,
+            tolerance,
+        );
+        gmm.covariance_model = model;
+        // Data drawn from G components often collapses one of the G + 1 components
+        gmm.degenerate_policy = DegeneratePolicy::Reseed;
+        gmm.fit(rows, distance, None, None).map(|_| gmm)
+    };
+
+    let two = T::from(2.0).unwrap();
+    let mut test = LikelihoodRatioTest {
+        components: Vec::new(),
+        statistics: Vec::new(),
+        p_values: Vec::new(),
+        bootstrap_statistics: Vec::new(),
+    };
+
+    for g in 1..=max_components {
+        let null = fit(data, g)?;
+        let alternative = fit(data, g + 1)?;
+        let statistic = two * (alternative.log_likelihood - null.log_likelihood);
+
+        let bootstrap_statistics: Vec<T> = (0..replicates)
+            .into_par_iter()
+            .filter_map(|replicate| {
+                let (rows, _) = null
+                    .sample(data.rows, seed.wrapping_add(replicate as u64))
+                    .ok()?;
+                let null = fit(&rows, g).ok()?;
+                let alternative = fit(&rows, g + 1).ok()?;
+
+                Some(two * (alternative.log_likelihood - null.log_likelihood))
+            })
+            .collect();
+
+        if bootstrap_statistics.is_empty() {
+            return Err("None of the replicates could be fitted");
+        }
+
+        let larger = bootstrap_statistics
+            .iter()
+            .filter(|b| **b >= statistic)
+            .count();
+        let p_value =
+            T::from(1 + larger).unwrap() / T::from(1 + bootstrap_statistics.len()).unwrap();
+
+        test.components.push(g);
+        test.statistics.push(statistic);
+        test.p_values.push(p_value);
+        test.bootstrap_statistics.push(bootstrap_statistics);
+
+        if p_value > significance {
+            break;
+        }
+    }
+
+    Ok(test)
+}
@@ -4,17 +4,31 @@ use std::iter::Sum;
 use std::ops::{Add, Div, Mul, Sub};
 use std::str::FromStr;
 
-use super::distances::{eucleadian_distance, Distance};
+use super::distances::{Distance, SquaredEuclidean};
 use super::gmm::GaussianMixtureModel;
+use super::mfa::MixtureOfFactorAnalyzers;
+use super::mixture::MixtureModel;
+use super::tmm::StudentTMixtureModel;
 
 use crate::calculations::matrix::Matrix;
-use crate::training_setup::TrainingSetup;
+use crate::training_setup::{ClusteringModel, TrainingSetup};
 
 /// Struct storing the information need for the ClustVarSel algorithm
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize + Float",
+        deserialize = "T: serde::Deserialize<'de> + Float"
+    ))
+)]
 pub struct CLUSTVARSEL<T> {
     pub final_selection: Vec<usize>,
+    #[cfg_attr(feature = "serde", serde(with = "super::persistence::non_finite"))]
     pub best_bic: T,
     training_setup: TrainingSetup<T>,
+    labels: Option<Vec<Option<usize>>>,
+    weights: Option<Vec<T>>,
 }
 
 impl<
@@ -57,15 +71,95 @@ where
                 initial_mixtures,
                 verbose,
                 cores,
+                model: ClusteringModel::Gaussian,
             },
+            labels: None,
+            weights: None,
         }
-    }  
+    }
+
+    /// Sets the mixture model used to cluster the candidate variables
+    pub fn set_model(&mut self, model: ClusteringModel) {
+        self.training_setup.model = model;
+    }
+
+    /// Sets the known labels, None for the rows whose cluster is unknown
+    ///
+    /// The selection then runs in the semi-supervised setting.
+    pub fn set_labels(&mut self, labels: Option<Vec<Option<usize>>>) {
+        self.labels = labels;
+    }
+
+    /// Sets the weight of each row, integer weights act as repeated rows
+    pub fn set_weights(&mut self, weights: Option<Vec<T>>) {
+        self.weights = weights;
+    }
+
+    /// Returns a new, unfitted, clustering model
+    fn new_model(&self) -> Box<dyn MixtureModel<T>> {
+        match self.training_setup.model {
+            ClusteringModel::Gaussian => Box::new(GaussianMixtureModel::new(
+                self.training_setup.number_clusters,
+                self.training_setup.seed,
+                self.training_setup.initial_mixtures.clone(),
+                self.training_setup.max_steps,
+                self.training_setup.tolerance,
+            )),
+            ClusteringModel::StudentT {
+                shared_degrees_of_freedom,
+            } => Box::new(StudentTMixtureModel::new(
+                self.training_setup.number_clusters,
+                self.training_setup.seed,
+                self.training_setup.initial_mixtures.clone(),
+                self.training_setup.max_steps,
+                self.training_setup.tolerance,
+                shared_degrees_of_freedom,
+            )),
+            ClusteringModel::FactorAnalyzers {
+                factors,
+                shared_uniquenesses,
+            } => Box::new(MixtureOfFactorAnalyzers::new(
+                self.training_setup.number_clusters,
+                factors,
+                self.training_setup.seed,
+                self.training_setup.initial_mixtures.clone(),
+                self.training_setup.max_steps,
+                self.training_setup.tolerance,
+                shared_uniquenesses,
+            )),
+        }
+    }
 
     /// Performs selection and fitting on the data
     ///
     /// This is the main loop performing attribute selection and removal
     /// Once finished, the struct contains the selected attributes
     pub fn fit(&mut self, data: Matrix<T>) -> Result<String, String> {
+        if let Some(labels) = &self.labels {
+            if labels.len() != data.rows {
+                return Err("The labels do not match the number of rows".to_string());
+            }
+        }
+
+        if let Some(weights) = &self.weights {
+            if weights.len() != data.rows {
+                return Err("The weights do not match the number of rows".to_string());
+            }
+        }
+
+        // Only the GaussianMixtureModel supports known labels and row weights
+        if self.training_setup.model != ClusteringModel::Gaussian {
+            if self.labels.is_some() {
+                return Err("Known labels are only supported by the Gaussian model".to_string());
+            }
+
+            if self.weights.is_some() {
+                return Err("Row weights are only supported by the Gaussian model".to_string());
+            }
+        }
+
+        self.best_bic = T::infinity();
+
         let mut selected_columns = Vec::with_capacity(data.columns);
         let mut current_matrix: Matrix<T> = Matrix::empty();
 
@@ -76,60 +170,26 @@ where
             let mut bic_add: Vec<T> = Vec::new();
 
             // Addition step
-            (0..data.columns).into_iter().for_each(|to_add| {
-                if !selected_columns.contains(&to_add) {
-                    let mut local_matrix: Matrix<T> = current_matrix.clone();
-                    local_matrix
-                        .append_vector(&data.get_col(to_add).content, 1)
-                        .expect("Unable to append column");
-
-                    let mut gmm = GaussianMixtureModel::new(
-                        self.training_setup.number_clusters,
-                        self.training_setup.seed,
-                        self.training_setup.initial_mixtures.clone(),
-                        self.training_setup.max_steps,
-                        self.training_setup.tolerance,
-                    );
-
-                    let distance: Distance<T> = eucleadian_distance;
-
-                    match gmm.fit(&local_matrix, distance) {
-                        Ok(msg) => {
-                            if self.training_setup.verbose {
-                                println!(
-                                    "- {}",
-                                    msg.to_owned()
-                                        + " adding: "
-                                        + &to_add.to_string()
-                                        + "\n Steps: "
-                                        + &gmm.steps.to_string()
-                                        + "\n Final Difference: "
-                                        + &gmm.final_difference.to_string()
-                                )
-                            }
-                        }
-                        Err(msg) => {
-                            let message: String = msg.to_owned()
-                                + "\n Steps: "
-                                + &gmm.steps.to_string()
-                                + "\n Final Difference: "
-                                + &gmm.final_difference.to_string();
-                            panic!("{}", message)
-                        }
-                    };
+            for to_add in 0..data.columns {
+                if selected_columns.contains(&to_add) {
+                    bic_add.push(T::infinity());
+                    continue;
+                }
 
-                    let b = self.bic(gmm, local_matrix);
+                let mut local_matrix: Matrix<T> = current_matrix.clone();
+                local_matrix
+                    .append_vector(&data.get_col(to_add).content, 1)
+                    .expect("Unable to append column");
 
-                    println!(" BIC: {b:?}");
+                let b = self.candidate_bic(&local_matrix, &format!(" adding: {to_add}"))?;
 
-                    bic_add.push(b);
-                } else {
-                    bic_add.push(T::from(100).unwrap());
-                }
-            });
+                println!(" BIC: {b:?}");
+
+                bic_add.push(b);
+            }
 
             let argmin_bic_add = bic_add.iter().enumerate().fold(
-                (0usize, T::from(100).unwrap()),
+                (0usize, T::infinity()),
                 |current, possible_new| {
                     if &current.1 > possible_new.1 {
                         (possible_new.0, possible_new.1.to_owned())
@@ -154,50 +214,17 @@ where
 
             // Removal step
             if selected_columns.len() > 1 {
-                selected_columns.iter().for_each(|attr_index| {
+                for attr_index in &selected_columns {
                     let mut local_matrix = current_matrix.clone();
                     local_matrix
                         .remove(attr_index.to_owned(), 1)
                         .expect("Unable to remove a column");
 
-                    let mut gmm = GaussianMixtureModel::new(
-                        self.training_setup.number_clusters,
-                        self.training_setup.seed,
-                        self.training_setup.initial_mixtures.clone(),
-                        self.training_setup.max_steps,
-                        self.training_setup.tolerance,
-                    );
-
-                    let distance: Distance<T> = eucleadian_distance;
-
-                    match gmm.fit(&local_matrix, distance) {
-                        Ok(msg) => {
-                            if self.training_setup.verbose {
-                                println!(
-                                    "{}",
-                                    msg.to_owned()
-                                        + "\n Steps: "
-                                        + &gmm.steps.to_string()
-                                        + "\n Final Difference: "
-                                        + &gmm.final_difference.to_string()
-                                )
-                            }
-                        }
-                        Err(msg) => {
-                            let message: String = msg.to_owned()
-                                + "\n Steps: "
-                                + &gmm.steps.to_string()
-                                + "\n Final Difference: "
-                                + &gmm.final_difference.to_string();
-                            panic!("{}", message)
-                        }
-                    };
-
-                    bic_remove.push(self.bic(gmm, local_matrix));
-                });
+                    bic_remove.push(self.candidate_bic(&local_matrix, "")?);
+                }
 
                 let argmin_bic_remove = bic_remove.iter().enumerate().fold(
-                    (0usize, T::from(100).unwrap()),
+                    (0usize, T::infinity()),
                     |current, possible_new| {
                         if &current.1 > possible_new.1 {
                             (possible_new.0, possible_new.1.to_owned())
@@ -236,15 +263,46 @@ where
         }
     }
 
-    pub fn bic(&self, model: GaussianMixtureModel<T>, data: Matrix<T>) -> T {
-        let ck: T = T::from(
-            self.training_setup.number_clusters * data.columns
-                + (self.training_setup.number_clusters - 1)
-                + self.training_setup.number_clusters * data.columns * (data.columns + 1) / 2,
-        )
-        .unwrap();
-        let log_likelihood: T = (model.gammas.sum(0).unwrap().sum(1).unwrap().content[0]).ln();
+    /// Fits a new clustering model to the candidate columns and returns its BIC
+    fn candidate_bic(&self, data: &Matrix<T>, action: &str) -> Result<T, String> {
+        let mut gmm = self.new_model();
+
+        let distance: &dyn Distance<T> = &SquaredEuclidean;
+
+        let fitted = gmm.fit(
+            data,
+            distance,
+            self.labels.as_deref(),
+            self.weights.as_deref(),
+        );
+        let details = "\n Steps: ".to_owned()
+            + &gmm.steps().to_string()
+            + "\n Final Difference: "
+            + &gmm.final_difference().to_string();
+
+        match fitted {
+            Ok(msg) => {
+                if self.training_setup.verbose {
+                    println!("- {}", msg.to_owned() + action + &details)
+                }
+            }
+            Err(msg) => return Err(msg.to_owned() + &details),
+        }
+
+        Ok(self.bic(gmm.as_ref(), data))
+    }
+
+    /// Bayesian Information Criterion of a fitted model, lower is better
+    ///
+    /// Weighted rows count as many times as their weight, in the log likelihood
+    /// of the model as in the number of observations.
+    pub fn bic(&self, model: &dyn MixtureModel<T>, data: &Matrix<T>) -> T {
+        let ck: T = T::from(model.number_parameters()).unwrap();
+        let n: T = match &self.weights {
+            Some(weights) => weights.iter().fold(T::zero(), |acc, w| acc + *w),
+            None => T::from(data.rows).unwrap(),
+        };
 
-        -T::from(2.0).unwrap() * log_likelihood + T::from(data.columns).unwrap() * ck.ln()
+        -T::from(2.0).unwrap() * model.log_likelihood() + ck * n.ln()
     }
 }
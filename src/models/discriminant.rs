@@ -0,0 +1,424 @@
+//! Model-based discriminant analysis (MclustDA)
+//!
+//! Every class is described by its own Gaussian mixture, new rows are given to
+//! the class with the largest posterior probability. The variables used by the
+//! classifier can be chosen with the stepwise search of CLUSTVARSEL, using the
+//! cross-validated error as criterion.
+
+use std::fmt::Debug;
+use std::iter::Sum;
+use std::ops::{Add, Div, Mul, Sub};
+use std::str::FromStr;
+
+use num::Float;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use super::distances::Distance;
+use super::gmm::{CovarianceModel, GaussianMixtureModel};
+
+use crate::calculations::matrix::Matrix;
+use crate::calculations::stats::normalize_log_densities;
+
+/// Struct containing all the information about the discriminant analysis
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize + Float",
+        deserialize = "T: serde::Deserialize<'de> + Float"
+    ))
+)]
+pub struct DiscriminantAnalysis<T> {
+    /// Number of components of each class mixture
+    components: usize,
+    seed: u64,
+    max_steps: i32,
+    #[cfg_attr(feature = "serde", serde(with = "super::persistence::non_finite"))]
+    tolerance: T,
+    pub covariance_model: CovarianceModel,
+    /// One mixture per class
+    pub classes: Vec<GaussianMixtureModel<T>>,
+    /// Proportion of the training rows in each class
+    pub priors: Vec<T>,
+    /// Columns used by the classifier, every column when None
+    pub variables: Option<Vec<usize>>,
+    /// Cross-validated error after every step of the variable selection
+    pub selection_errors: Vec<T>,
+}
+
+impl<
+        T: Float
+            + Div
+            + Mul
+            + Add
+            + Sub<Output = T>
+            + Sum<<T as Mul>::Output>
+            + Copy
+            + Debug
+            + Sync
+            + Send
+            + PartialOrd
+            + 'static,
+    > DiscriminantAnalysis<T>
+where
+    for<'a> &'a T: Sub<&'a T, Output = T>,
+    T: FromStr,
+    Vec<T>: FromIterator<<T as Div>::Output>,
+{
+    /// Returns a new DiscriminantAnalysis struct
+    pub fn new(
+        components: usize,
+        seed: u64,
+        max_steps: i32,
+        tolerance: T,
+    ) -> DiscriminantAnalysis<T> {
+        Self {
+            components,
+            seed,
+            max_steps,
+            tolerance,
+            covariance_model: CovarianceModel::VVV,
+            classes: Vec::new(),
+            priors: Vec::new(),
+            variables: None,
+            selection_errors: Vec::new(),
+        }
+    }
+
+    /// Returns the number of classes seen during the fit
+    pub fn number_classes(&self) -> usize {
+        self.classes.len()
+    }
+
+    /// Returns the columns of the data used by the classifier
+    fn select_columns(data: &Matrix<T>, variables: &Option<Vec<usize>>) -> Matrix<T> {
+        match variables {
+            Some(variables) => {
+                let mut selected: Matrix<T> = Matrix::empty();
+                variables.iter().for_each(|col| {
+                    selected
+                        .append_vector(&data.get_col(*col).content, 1)
+                        .expect("Unable to append column");
+                });
+                selected
+            }
+            None => data.clone(),
+        }
+    }
+
+    /// Fits one GaussianMixtureModel per class on the rows of that class
+    ///
+    /// Classes are numbered from 0 without gaps and every class needs at least as
+    /// many rows as there are components.
+    pub fn fit(
+        &mut self,
+        data: &Matrix<T>,
+        labels: &[usize],
+        distance: &dyn Distance<T>,
+    ) -> Result<&'static str, &'static str> {
+        if labels.len() != data.rows {
+            return Err("The labels do not match the number of rows");
+        }
+
+        if self
+            .variables
+            .iter()
+            .flatten()
+            .any(|col| *col >= data.columns)
+        {
+            return Err("A selected variable is larger than the number of columns");
+        }
+
+        let data = Self::select_columns(data, &self.variables);
+        let number_classes = labels.iter().max().map_or(0, |max| max + 1);
+
+        let mut used = vec![false; number_classes];
+        labels.iter().for_each(|label| used[*label] = true);
+
+        if used.contains(&false) {
+            return Err("Class labels must be contiguous from 0");
+        }
+
+        let mut classes = Vec::with_capacity(number_classes);
+        let mut priors = Vec::with_capacity(number_classes);
+
+        for class in 0..number_classes {
+            let mut rows: Matrix<T> = Matrix::empty();
+            labels.iter().enumerate().for_each(|(row, label)| {
+                if *label == class {
+                    rows.append_vector(&data[row], 0)
+                        .expect("Unable to append row");
+                }
+            });
+
+            if rows.rows < self.components {
+                return Err("A class has fewer rows than components");
+            }
+
+            let mut gmm = GaussianMixtureModel::new(
+                self.components,
+                self.seed,
+                vec![T::one() / T::from(self.components).unwrap(); self.components],
+                self.max_steps,
+                self.tolerance,
+            );
+            gmm.covariance_model = self.covariance_model;
+            gmm.fit(&rows, distance, None, None)?;
+
+            priors.push(T::from(rows.rows).unwrap() / T::from(data.rows).unwrap());
+            classes.push(gmm);
+        }
+
+        self.classes = classes;
+        self.priors = priors;
+
+        Ok("Fitted")
+    }
+
+    /// Returns the posterior probability of each class (rows) for each data point (columns)
+    pub fn predict_proba(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        if self.classes.is_empty() {
+            return Err("The model has not been fitted");
+        }
+
+        let data = Self::select_columns(data, &self.variables);
+        let mut posteriors: Matrix<T> = Matrix::zeroes(self.classes.len(), data.rows);
+
+        for (class, gmm) in self.classes.iter().enumerate() {
+            let log_prior = self.priors[class].ln();
+            gmm.score_samples(&data)?
+                .iter()
+                .enumerate()
+                .for_each(|(idx, density)| posteriors[class][idx] = *density + log_prior);
+        }
+
+        normalize_log_densities(&mut posteriors);
+
+        Ok(posteriors)
+    }
+
+    /// Returns the most likely class of each data point
+    pub fn predict(&self, data: &Matrix<T>) -> Result<Vec<usize>, &'static str> {
+        let probabilities = self.predict_proba(data)?;
+
+        Ok((0..probabilities.columns)
+            .map(|col| {
+                (0..probabilities.rows).fold(0, |best, row| {
+                    if probabilities[row][col] > probabilities[best][col] {
+                        row
+                    } else {
+                        best
+                    }
+                })
+            })
+            .collect())
+    }
+
+    /// Returns the proportion of misclassified rows
+    pub fn error_rate(&self, data: &Matrix<T>, labels: &[usize]) -> Result<T, &'static str> {
+        if labels.len() != data.rows {
+            return Err("The labels do not match the number of rows");
+        }
+
+        let wrong = self
+            .predict(data)?
+            .iter()
+            .zip(labels)
+            .filter(|(predicted, label)| predicted != label)
+            .count();
+
+        Ok(T::from(wrong).unwrap() / T::from(data.rows).unwrap())
+    }
+
+    /// Returns the cross-validated error of the classifier
+    ///
+    /// The rows are shuffled with the seed and split into `folds` parts, each
+    /// part is classified by the model fitted on the others. The fitted model
+    /// is left untouched.
+    pub fn cross_validation(
+        &self,
+        data: &Matrix<T>,
+        labels: &[usize],
+        folds: usize,
+        distance: &dyn Distance<T>,
+    ) -> Result<T, &'static str> {
+        self.cross_validation_with(data, labels, folds, distance, &self.variables)
+    }
+
+    /// Cross-validated error using the given columns
+    fn cross_validation_with(
+        &self,
+        data: &Matrix<T>,
+        labels: &[usize],
+        folds: usize,
+        distance: &dyn Distance<T>,
+        variables: &Option<Vec<usize>>,
+    ) -> Result<T, &'static str> {
+        if labels.len() != data.rows {
+            return Err("The labels do not match the number of rows");
+        }
+
+        if folds < 2 || folds > data.rows {
+            return Err("The number of folds has to be between 2 and the number of rows");
+        }
+
+        let mut order: Vec<usize> = (0..data.rows).collect();
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(self.seed);
+        order.shuffle(&mut rng);
+
+        let mut wrong: usize = 0;
+
+        for fold in 0..folds {
+            let mut train: Matrix<T> = Matrix::empty();
+            let mut train_labels: Vec<usize> = Vec::new();
+            let mut test: Matrix<T> = Matrix::empty();
+            let mut test_labels: Vec<usize> = Vec::new();
+
+            order.iter().enumerate().for_each(|(position, row)| {
+                if position % folds == fold {
+                    test.append_vector(&data[*row], 0)
+                        .expect("Unable to append row");
+                    test_labels.push(labels[*row]);
+                } else {
+                    train
+                        .append_vector(&data[*row], 0)
+                        .expect("Unable to append row");
+                    train_labels.push(labels[*row]);
+                }
+            });
+
+            // A class missing from the training folds can never be predicted,
+            // the classes left are numbered from 0 for the fit
+            let mut present: Vec<usize> = train_labels.clone();
+            present.sort_unstable();
+            present.dedup();
+            let train_labels: Vec<usize> = train_labels
+                .iter()
+                .map(|label| present.binary_search(label).expect("The label is present"))
+                .collect();
+
+            let mut model = DiscriminantAnalysis::new(
+                self.components,
+                self.seed,
+                self.max_steps,
+                self.tolerance,
+            );
+            model.covariance_model = self.covariance_model;
+            model.variables = variables.clone();
+            model.fit(&train, &train_labels, distance)?;
+
+            wrong += model
+                .predict(&test)?
+                .iter()
+                .zip(&test_labels)
+                .filter(|(predicted, label)| present[**predicted] != **label)
+                .count();
+        }
+
+        Ok(T::from(wrong).unwrap() / T::from(data.rows).unwrap())
+    }
+
+    /// Selects the variables with the stepwise search of CLUSTVARSEL and fits the classifier
+    ///
+    /// Each step adds the column lowering the cross-validated error the most, then
+    /// removes a column if doing so lowers it further. The search stops once no
+    /// addition or removal improves the error. Returns the selected columns.
+    pub fn select_variables(
+        &mut self,
+        data: &Matrix<T>,
+        labels: &[usize],
+        folds: usize,
+        distance: &dyn Distance<T>,
+    ) -> Result<Vec<usize>, &'static str> {
+        if labels.len() != data.rows {
+            return Err("The labels do not match the number of rows");
+        }
+
+        if data.rows == 0 {
+            return Err("The data has no rows");
+        }
+
+        // Without any variable every row goes to the largest class
+        let number_classes = labels.iter().max().map_or(0, |max| max + 1);
+        let largest = (0..number_classes)
+            .map(|class| labels.iter().filter(|label| **label == class).count())
+            .max()
+            .unwrap_or(0);
+        let mut best_error = T::from(data.rows - largest).unwrap() / T::from(data.rows).unwrap();
+
+        let mut selected: Vec<usize> = Vec::new();
+        self.selection_errors = vec![best_error];
+
+        loop {
+            let mut added: bool = false;
+            let mut removed: bool = false;
+
+            // Addition step
+            let mut best_addition: Option<(usize, T)> = None;
+            for to_add in (0..data.columns).filter(|col| !selected.contains(col)) {
+                let mut candidate = selected.clone();
+                candidate.push(to_add);
+
+                let error =
+                    self.cross_validation_with(data, labels, folds, distance, &Some(candidate))?;
+
+                if best_addition.is_none_or(|(_, best)| error < best) {
+                    best_addition = Some((to_add, error));
+                }
+            }
+
+            if let Some((to_add, error)) = best_addition {
+                if error < best_error {
+                    selected.push(to_add);
+                    best_error = error;
+                    self.selection_errors.push(error);
+                    added = true;
+                }
+            }
+
+            // Removal step
+            if selected.len() > 1 {
+                let mut best_removal: Option<(usize, T)> = None;
+                for to_remove in 0..selected.len() {
+                    let mut candidate = selected.clone();
+                    candidate.remove(to_remove);
+
+                    let error = self.cross_validation_with(
+                        data,
+                        labels,
+                        folds,
+                        distance,
+                        &Some(candidate),
+                    )?;
+
+                    if best_removal.is_none_or(|(_, best)| error < best) {
+                        best_removal = Some((to_remove, error));
+                    }
+                }
+
+                if let Some((to_remove, error)) = best_removal {
+                    if error < best_error {
+                        selected.remove(to_remove);
+                        best_error = error;
+                        self.selection_errors.push(error);
+                        removed = true;
+                    }
+                }
+            }
+
+            if !added && !removed || selected.len() == data.columns {
+                break;
+            }
+        }
+
+        if selected.is_empty() {
+            return Err("None of the variables lowers the error of the prior classifier");
+        }
+
+        self.variables = Some(selected.clone());
+        self.fit(data, labels, distance)?;
+
+        Ok(selected)
+    }
+}
@@ -1,16 +1,54 @@
+//! Distances used to compare rows
+//!
+//! Every metric implements the Distance trait, so that stateful metrics such as
+//! Mahalanobis can keep what they need between calls. Functions and closures
+//! with the right signature are metrics too.
+
 use std::{
+    fmt::Debug,
     iter::{zip, Sum},
     ops::{Mul, Sub},
 };
 
 use num::Float;
 
-use crate::calculations::linear_algebra::dot_product;
+use crate::calculations::linear_algebra::cholesky_inverse;
+use crate::calculations::matrix::Matrix;
+
+/// Distance between two rows
+pub trait Distance<T>: Send + Sync {
+    /// Computes the distance between a and b
+    fn distance(&self, a: &[T], b: &[T]) -> Result<T, &'static str>;
+
+    /// Returns a boxed copy of the metric, so that models can keep it after the fit
+    fn boxed(&self) -> Box<dyn Distance<T>>;
+}
+
+impl<T, F> Distance<T> for F
+where
+    F: Fn(&[T], &[T]) -> Result<T, &'static str> + Clone + Send + Sync + 'static,
+{
+    fn distance(&self, a: &[T], b: &[T]) -> Result<T, &'static str> {
+        self(a, b)
+    }
+
+    fn boxed(&self) -> Box<dyn Distance<T>> {
+        Box::new(self.clone())
+    }
+}
+
+/// Returns an error if the rows do not have the same length
+fn check_lengths<T>(a: &[T], b: &[T]) -> Result<(), &'static str> {
+    if a.len() != b.len() {
+        return Err("The vector size do not match");
+    }
 
-/// Type of a distance computation function
-pub type Distance<T> = fn(&[T], &[T]) -> Result<T, &'static str>;
+    Ok(())
+}
 
 /// Eucleadian distance computation
+///
+/// The distance is squared, it is the one Kmeans minimises.
 pub fn eucleadian_distance<T: Float + Sum<<T as Mul>::Output> + Clone>(
     a: &[T],
     b: &[T],
@@ -18,7 +56,175 @@ pub fn eucleadian_distance<T: Float + Sum<<T as Mul>::Output> + Clone>(
 where
     for<'a> &'a T: Sub<&'a T, Output = T>,
 {
-    let tmp: Vec<T> = zip(a, b).map(|(i, k)| i - k).collect();
+    check_lengths(a, b)?;
+
+    Ok(zip(a, b).fold(T::zero(), |acc, (i, k)| {
+        let diff = i - k;
+        acc + diff * diff
+    }))
+}
+
+/// Squared eucleadian distance, the default metric of Kmeans
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SquaredEuclidean;
+
+impl<T: Float + Send + Sync + 'static> Distance<T> for SquaredEuclidean {
+    fn distance(&self, a: &[T], b: &[T]) -> Result<T, &'static str> {
+        check_lengths(a, b)?;
+
+        Ok(zip(a, b).fold(T::zero(), |acc, (i, k)| acc + (*i - *k) * (*i - *k)))
+    }
+
+    fn boxed(&self) -> Box<dyn Distance<T>> {
+        Box::new(*self)
+    }
+}
+
+/// Eucleadian distance
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Euclidean;
+
+impl<T: Float + Send + Sync + 'static> Distance<T> for Euclidean {
+    fn distance(&self, a: &[T], b: &[T]) -> Result<T, &'static str> {
+        Ok(SquaredEuclidean.distance(a, b)?.sqrt())
+    }
+
+    fn boxed(&self) -> Box<dyn Distance<T>> {
+        Box::new(*self)
+    }
+}
+
+/// Sum of the absolute differences
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Manhattan;
+
+impl<T: Float + Send + Sync + 'static> Distance<T> for Manhattan {
+    fn distance(&self, a: &[T], b: &[T]) -> Result<T, &'static str> {
+        check_lengths(a, b)?;
+
+        Ok(zip(a, b).fold(T::zero(), |acc, (i, k)| acc + (*i - *k).abs()))
+    }
+
+    fn boxed(&self) -> Box<dyn Distance<T>> {
+        Box::new(*self)
+    }
+}
+
+/// One minus the cosine of the angle between the rows
+///
+/// It is not defined when one of the rows is null.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cosine;
+
+impl<T: Float + Send + Sync + 'static> Distance<T> for Cosine {
+    fn distance(&self, a: &[T], b: &[T]) -> Result<T, &'static str> {
+        check_lengths(a, b)?;
+
+        let (dot, norm_a, norm_b) = zip(a, b).fold(
+            (T::zero(), T::zero(), T::zero()),
+            |(dot, norm_a, norm_b), (i, k)| (dot + *i * *k, norm_a + *i * *i, norm_b + *k * *k),
+        );
+
+        if norm_a == T::zero() || norm_b == T::zero() {
+            return Err("The cosine distance is not defined for a null vector");
+        }
+
+        Ok(T::one() - dot / (norm_a.sqrt() * norm_b.sqrt()))
+    }
+
+    fn boxed(&self) -> Box<dyn Distance<T>> {
+        Box::new(*self)
+    }
+}
+
+/// Minkowski distance of order p, Manhattan for p = 1 and eucleadian for p = 2
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Minkowski<T> {
+    p: T,
+}
+
+impl<T: Float> Minkowski<T> {
+    /// Returns the Minkowski distance of order p, which has to be at least 1
+    pub fn new(p: T) -> Result<Minkowski<T>, &'static str> {
+        if p.is_nan() || p < T::one() {
+            return Err("The order of the Minkowski distance has to be at least 1");
+        }
+
+        Ok(Minkowski { p })
+    }
+
+    /// Returns the order of the distance
+    pub fn p(&self) -> T {
+        self.p
+    }
+}
+
+impl<T: Float + Send + Sync + 'static> Distance<T> for Minkowski<T> {
+    fn distance(&self, a: &[T], b: &[T]) -> Result<T, &'static str> {
+        check_lengths(a, b)?;
+
+        let sum = zip(a, b).fold(T::zero(), |acc, (i, k)| acc + (*i - *k).abs().powf(self.p));
+
+        Ok(sum.powf(T::one() / self.p))
+    }
+
+    fn boxed(&self) -> Box<dyn Distance<T>> {
+        Box::new(*self)
+    }
+}
+
+/// Mahalanobis distance, the eucleadian distance once the covariance is taken out
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mahalanobis<T> {
+    inverse_covariance: Matrix<T>,
+}
+
+impl<T: Float + Debug + Send + Sync + Sum + 'static> Mahalanobis<T> {
+    /// Returns the Mahalanobis distance of a positive definite covariance matrix
+    pub fn new(covariance: &Matrix<T>) -> Result<Mahalanobis<T>, &'static str> {
+        if covariance.rows != covariance.columns {
+            return Err("The covariance matrix has to be square");
+        }
+
+        let inverse_covariance = cholesky_inverse(covariance)
+            .map_err(|_| "The covariance matrix is not positive definite")?;
+
+        Ok(Mahalanobis { inverse_covariance })
+    }
+
+    /// Returns the inverse of the covariance matrix used by the distance
+    pub fn inverse_covariance(&self) -> &Matrix<T> {
+        &self.inverse_covariance
+    }
+}
+
+impl<T: Float + Debug + Send + Sync + Sum + 'static> Distance<T> for Mahalanobis<T> {
+    fn distance(&self, a: &[T], b: &[T]) -> Result<T, &'static str> {
+        check_lengths(a, b)?;
+
+        if a.len() != self.inverse_covariance.rows {
+            return Err("The vector size do not match the covariance matrix");
+        }
+
+        let squared = (0..a.len()).fold(T::zero(), |acc, row| {
+            let diff_row = a[row] - b[row];
+            (0..a.len()).fold(acc, |acc, col| {
+                acc + diff_row * self.inverse_covariance[row][col] * (a[col] - b[col])
+            })
+        });
+
+        // Rounding can leave a tiny negative value for identical rows
+        Ok(squared.max(T::zero()).sqrt())
+    }
 
-    dot_product(&tmp, &tmp)
+    fn boxed(&self) -> Box<dyn Distance<T>> {
+        Box::new(Mahalanobis {
+            inverse_covariance: self.inverse_covariance.clone(),
+        })
+    }
 }
@@ -4,27 +4,264 @@ use std::ops::{Add, Div, Mul, Sub};
 use std::str::FromStr;
 
 use num::Float;
+use rand::{Rng, SeedableRng};
 
-use super::{distances, kmeans};
+use super::mixture::{fix_known_labels, MixtureModel};
+use super::{distances, kmeans, kmedoids};
 
+use crate::calculations::linear_algebra::{
+    backward_substitution, cholesky_decomposition, cholesky_log_determinant, dot_product,
+    forward_substitution,
+};
 use crate::calculations::matrix::Matrix;
+use crate::calculations::partition::Partition;
 use crate::calculations::stats::{
-    means_zs, covariance_no_z, slow_multivariate_gaussian, covariance,
+    covariance_no_z, hypervolume, log_multivariate_gaussian, normalize_log_densities,
+    weighted_covariance, weighted_means_zs, weighted_normalize_log_densities, WeightKind,
 };
 use crate::helpers::mean_squared_error;
 
+/// Parametrisation of the component covariance matrices
+///
+/// Follows the mclust nomenclature: the letters give the volume, shape and
+/// orientation, either Equal, Variable or Identity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CovarianceModel {
+    /// Spherical, equal volume
+    EII,
+    /// Spherical, varying volume
+    VII,
+    /// Diagonal, equal volume and shape
+    EEI,
+    /// Diagonal, varying volume and shape
+    VVI,
+    /// Ellipsoidal, equal volume, shape and orientation
+    EEE,
+    /// Ellipsoidal, varying volume, shape and orientation
+    VVV,
+}
+
+impl CovarianceModel {
+    /// Every covariance model, from the most to the least constrained
+    pub const ALL: [CovarianceModel; 6] = [
+        CovarianceModel::EII,
+        CovarianceModel::VII,
+        CovarianceModel::EEI,
+        CovarianceModel::VVI,
+        CovarianceModel::EEE,
+        CovarianceModel::VVV,
+    ];
+
+    /// Number of free parameters used by the covariances of k components in dimension p
+    pub fn number_parameters(&self, k: usize, p: usize) -> usize {
+        match self {
+            CovarianceModel::EII => 1,
+            CovarianceModel::VII => k,
+            CovarianceModel::EEI => p,
+            CovarianceModel::VVI => k * p,
+            CovarianceModel::EEE => p * (p + 1) / 2,
+            CovarianceModel::VVV => k * p * (p + 1) / 2,
+        }
+    }
+}
+
+/// Algorithm used to fit the GaussianMixtureModel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EmAlgorithm {
+    /// Expectation Maximisation with soft assignments
+    EM,
+    /// Classification EM, every row goes to its most likely component.
+    /// The classification likelihood it maximises is the one stored in log_likelihood.
+    CEM,
+    /// Stochastic EM, every row goes to a component sampled from its responsibilities.
+    /// It runs for max_steps and keeps the parameters with the best likelihood.
+    /// Components left with too few sampled rows are reseeded with the Fail policy.
+    SEM,
+}
+
+/// Clustering giving the starting components of the GaussianMixtureModel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Initialization {
+    /// Kmeans++ followed by Kmeans
+    Kmeans,
+    /// FasterPAM K-medoids, robust to outliers and to any choice of distance
+    Kmedoids,
+}
+
+/// What to do when a component collapses during EM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DegeneratePolicy {
+    /// Restart the component on a random data point with the pooled covariance
+    Reseed,
+    /// Remove the component and carry on with one cluster less
+    Drop,
+    /// Stop fitting and return an error
+    Fail,
+}
+
+/// The way a component collapsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Degeneracy {
+    /// No data point is responsible for the component anymore
+    Empty,
+    /// The covariance matrix has a null or negative determinant
+    SingularCovariance,
+    /// The mean or covariance contains NaN or infinite values
+    NonFinite,
+}
+
+/// Diagnostic recorded every time a degenerate component is detected
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize + Float",
+        deserialize = "T: serde::Deserialize<'de> + Float"
+    ))
+)]
+pub struct ComponentDiagnostic<T> {
+    pub component: usize,
+    pub step: u64,
+    pub kind: Degeneracy,
+    #[cfg_attr(feature = "serde", serde(with = "super::persistence::non_finite"))]
+    pub nk: T,
+    pub action: DegeneratePolicy,
+}
+
+/// Uniform noise component spread over the hypervolume of the data
+///
+/// Outliers are absorbed by this component instead of pulling the gaussian means.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NoiseComponent<T> {
+    /// Rows initially considered as noise, they are left out of the Kmeans initialisation
+    pub initial_noise: Vec<bool>,
+    /// Volume of the region the noise is spread over
+    pub hypervolume: T,
+    /// Mixing proportion of the noise once fitted
+    pub proportion: T,
+    /// Probability of each row to belong to the noise once fitted
+    pub membership: Vec<T>,
+}
+
+impl<T: Float + Debug + Send + Sync + Sum + 'static> NoiseComponent<T> {
+    /// Returns a noise component whose hypervolume is estimated from the data
+    pub fn new(initial_noise: Vec<bool>, data: &Matrix<T>) -> NoiseComponent<T> {
+        NoiseComponent {
+            initial_noise,
+            hypervolume: hypervolume(data),
+            proportion: T::zero(),
+            membership: Vec::new(),
+        }
+    }
+}
+
+/// Rows sharing the same missing coordinates
+struct MissingPattern {
+    observed: Vec<usize>,
+    missing: Vec<usize>,
+    rows: Vec<usize>,
+}
+
+impl MissingPattern {
+    /// Groups the rows of the data by their NaN entries
+    fn from_data<T: Float + 'static>(data: &Matrix<T>) -> Vec<MissingPattern> {
+        let mut patterns: Vec<MissingPattern> = Vec::new();
+
+        (0..data.rows).for_each(|row| {
+            let missing: Vec<usize> = (0..data.columns)
+                .filter(|col| data[row][*col].is_nan())
+                .collect();
+
+            match patterns
+                .iter_mut()
+                .find(|pattern| pattern.missing == missing)
+            {
+                Some(pattern) => pattern.rows.push(row),
+                None => patterns.push(MissingPattern {
+                    observed: (0..data.columns)
+                        .filter(|col| !missing.contains(col))
+                        .collect(),
+                    missing,
+                    rows: vec![row],
+                }),
+            }
+        });
+
+        patterns
+    }
+}
+
+/// Outcome of the E step over the observed coordinates only
+struct ConditionalExpectations<T> {
+    gammas: Matrix<T>,
+    log_likelihood: T,
+    /// Per component, the data with the missing entries replaced by their conditional mean
+    completed: Vec<Matrix<T>>,
+    /// Per component and pattern, the conditional covariance of the missing entries
+    conditional_covariances: Vec<Vec<Matrix<T>>>,
+}
+
+/// Running sufficient statistics of the online EM, averaged per row
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct OnlineStatistics<T> {
+    /// Responsibility of each component
+    s0: Vec<T>,
+    /// Responsibility weighted rows, one row per component
+    s1: Matrix<T>,
+    /// Responsibility weighted outer products of the rows, one matrix per component
+    s2: Vec<Matrix<T>>,
+    /// Number of chunks consumed
+    updates: u64,
+}
+
+/// Parameters of the SEM step with the best likelihood so far
+struct BestStep<T> {
+    log_likelihood: T,
+    k: usize,
+    means: Matrix<T>,
+    covariance_matrices: Vec<Matrix<T>>,
+    mixtures: Vec<T>,
+}
+
 /// Struct containing all the information about the Gaussian Mixture Model
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize + Float",
+        deserialize = "T: serde::Deserialize<'de> + Float"
+    ))
+)]
 pub struct GaussianMixtureModel<T> {
     means: Matrix<T>,
     covariance_matrices: Vec<Matrix<T>>,
     k: usize,
     seed: u64,
     mixtures: Vec<T>,
+    #[cfg_attr(feature = "serde", serde(with = "super::persistence::non_finite"))]
     tolerance: T,
     pub final_difference: f32,
     max_steps: i32,
     pub steps: u64,
     pub gammas: Matrix<T>,
+    #[cfg_attr(feature = "serde", serde(with = "super::persistence::non_finite"))]
+    pub log_likelihood: T,
+    pub covariance_model: CovarianceModel,
+    pub noise: Option<NoiseComponent<T>>,
+    pub degenerate_policy: DegeneratePolicy,
+    pub diagnostics: Vec<ComponentDiagnostic<T>>,
+    pub algorithm: EmAlgorithm,
+    pub initialization: Initialization,
+    /// Decay of the online EM step size, in (0.5, 1]
+    pub step_decay: T,
+    online: Option<OnlineStatistics<T>>,
 }
 
 impl<
@@ -67,51 +304,680 @@ where
             max_steps,
             steps: 0,
             gammas: Matrix::empty(),
+            log_likelihood: T::neg_infinity(),
+            covariance_model: CovarianceModel::VVV,
+            noise: None,
+            degenerate_policy: DegeneratePolicy::Fail,
+            diagnostics: Vec::new(),
+            algorithm: EmAlgorithm::EM,
+            initialization: Initialization::Kmeans,
+            step_decay: T::from(0.6).unwrap(),
+            online: None,
         }
     }
 
-    /// Fits the Gaussian Mixture Model to the data using EM
-    pub fn fit(
+    /// Returns an unfitted model with the same settings and the given seed
+    ///
+    /// The noise component is not carried over as it depends on the data.
+    pub fn new_like(&self, seed: u64) -> GaussianMixtureModel<T> {
+        let mut gmm = GaussianMixtureModel::new(
+            self.k,
+            seed,
+            vec![T::one() / T::from(self.k).unwrap(); self.k],
+            self.max_steps,
+            self.tolerance,
+        );
+        gmm.covariance_model = self.covariance_model;
+        gmm.degenerate_policy = self.degenerate_policy;
+        gmm.algorithm = self.algorithm;
+        gmm.initialization = self.initialization;
+        gmm.step_decay = self.step_decay;
+
+        gmm
+    }
+
+    /// Returns the number of components currently in the model
+    pub fn number_components(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the means of the components, one row per component
+    pub fn means(&self) -> &Matrix<T> {
+        &self.means
+    }
+
+    /// Returns the covariance matrix of each component
+    pub fn covariance_matrices(&self) -> &[Matrix<T>] {
+        &self.covariance_matrices
+    }
+
+    /// Returns the mixing proportions, the noise proportion comes last if any
+    pub fn mixtures(&self) -> &[T] {
+        &self.mixtures
+    }
+
+    /// Returns the number of free parameters of the fitted model
+    pub fn number_parameters(&self) -> usize {
+        let p = self.means.columns;
+        let noise = usize::from(self.noise.is_some());
+
+        self.k * p
+            + self.k.saturating_sub(1)
+            + noise
+            + self.covariance_model.number_parameters(self.k, p)
+    }
+
+    /// Computes the Bayesian Information Criterion of the fitted model
+    ///
+    /// Uses the mclust convention 2 * loglik - npar * ln(n), the larger the better.
+    pub fn bic(&self, rows: usize) -> T {
+        let two: T = T::from(2.0).unwrap();
+        let npar: T = T::from(self.number_parameters()).unwrap();
+
+        two * self.log_likelihood - npar * T::from(rows).unwrap().ln()
+    }
+
+    /// Applies the covariance model constraints to the component covariances
+    ///
+    /// Expects the unconstrained weighted covariances and the weight of each component.
+    fn constrain_covariances(&self, covs: Vec<Matrix<T>>, nk: &[T]) -> Vec<Matrix<T>> {
+        let p = covs[0].rows;
+        let total = nk.iter().fold(T::zero(), |acc, n| acc + *n);
+
+        let pooled = || {
+            let mut pooled: Matrix<T> = Matrix::zeroes(p, p);
+            covs.iter().zip(nk).for_each(|(cov, n)| {
+                (0..p * p).for_each(|idx| {
+                    pooled.content[idx] = pooled.content[idx] + cov.content[idx] * *n / total
+                })
+            });
+            pooled
+        };
+
+        let diagonal = |cov: &Matrix<T>| {
+            let mut diag: Matrix<T> = Matrix::zeroes(p, p);
+            (0..p).for_each(|idx| diag[idx][idx] = cov[idx][idx]);
+            diag
+        };
+
+        let spherical = |cov: &Matrix<T>| {
+            let trace = (0..p).fold(T::zero(), |acc, idx| acc + cov[idx][idx]);
+            let mut sphere: Matrix<T> = Matrix::zeroes(p, p);
+            (0..p).for_each(|idx| sphere[idx][idx] = trace / T::from(p).unwrap());
+            sphere
+        };
+
+        match self.covariance_model {
+            CovarianceModel::VVV => covs,
+            CovarianceModel::VVI => covs.iter().map(diagonal).collect(),
+            CovarianceModel::VII => covs.iter().map(spherical).collect(),
+            CovarianceModel::EEE => vec![pooled(); covs.len()],
+            CovarianceModel::EEI => vec![diagonal(&pooled()); covs.len()],
+            CovarianceModel::EII => vec![spherical(&pooled()); covs.len()],
+        }
+    }
+
+    /// Checks whether a component has collapsed
+    fn degeneracy(nk: T, mean: &[T], cov: &Matrix<T>, rows: usize) -> Option<Degeneracy> {
+        if !nk.is_finite() || nk <= T::epsilon() * T::from(rows).unwrap() {
+            return Some(Degeneracy::Empty);
+        }
+
+        if mean
+            .iter()
+            .chain(cov.content.iter())
+            .any(|val| !val.is_finite())
+        {
+            return Some(Degeneracy::NonFinite);
+        }
+
+        match cholesky_decomposition(cov) {
+            Ok(_) => None,
+            Err(_) => Some(Degeneracy::SingularCovariance),
+        }
+    }
+
+    /// Computes the responsibilities of each component and the log likelihood
+    ///
+    /// The densities are combined in log space so that points far from every
+    /// component do not underflow to 0. When there is a noise component, its
+    /// responsibilities are stored in the last row.
+    pub fn e_step(&self, data: &Matrix<T>) -> Result<(Matrix<T>, T), &'static str> {
+        self.weighted_e_step(data, &vec![T::one(); data.rows])
+    }
+
+    /// Same as e_step, each row counting as many times as its weight in the log likelihood
+    fn weighted_e_step(
+        &self,
+        data: &Matrix<T>,
+        weights: &[T],
+    ) -> Result<(Matrix<T>, T), &'static str> {
+        let mut gammas = self.log_joint_densities(data)?;
+        let log_likelihood = weighted_normalize_log_densities(&mut gammas, weights);
+
+        Ok((gammas, log_likelihood))
+    }
+
+    /// Returns the log of the mixture proportion times the density of each component (rows)
+    /// for each data point (columns)
+    fn log_joint_densities(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        let components = self.k + usize::from(self.noise.is_some());
+        let mut gammas: Matrix<T> = Matrix::zeroes(components, data.rows);
+
+        for i in 0..self.k {
+            let log_densities =
+                log_multivariate_gaussian(data, &self.covariance_matrices[i], &self.means[i])
+                    .map_err(|_| "Covariance matrix is not positive definite")?;
+            let log_mixture = self.mixtures[i].ln();
+
+            log_densities
+                .iter()
+                .enumerate()
+                .for_each(|(idx, density)| gammas[i][idx] = *density + log_mixture);
+        }
+
+        if let Some(noise) = &self.noise {
+            let log_noise = self.mixtures[self.k].ln() - noise.hypervolume.ln();
+            gammas[self.k]
+                .iter_mut()
+                .for_each(|gamma| *gamma = log_noise);
+        }
+
+        Ok(gammas)
+    }
+
+    /// Returns the log density of each data point under the fitted mixture
+    pub fn score_samples(&self, data: &Matrix<T>) -> Result<Vec<T>, &'static str> {
+        if self.means.is_empty() {
+            return Err("The model has not been fitted");
+        }
+
+        let log_densities = self.log_joint_densities(data)?;
+
+        Ok((0..data.rows)
+            .map(|idx| {
+                let max = (0..log_densities.rows)
+                    .fold(T::neg_infinity(), |acc, i| acc.max(log_densities[i][idx]));
+                let sum = (0..log_densities.rows).fold(T::zero(), |acc, i| {
+                    acc + (log_densities[i][idx] - max).exp()
+                });
+
+                max + sum.ln()
+            })
+            .collect())
+    }
+
+    /// Applies the degenerate policy to the given components
+    ///
+    /// The means, covariances and mixtures are updated in place.
+    fn recover(
         &mut self,
         data: &Matrix<T>,
-        distance: distances::Distance<T>,
-    ) -> Result<&'static str, &'static str> {
-        let mut init = kmeans::Kmeans::init(self.k, data, self.seed, distance);
-        init.fit(data);
+        weights: &[T],
+        means: &mut Matrix<T>,
+        covs: &mut Vec<Matrix<T>>,
+        mixtures: &mut Vec<T>,
+        degenerate: Vec<(usize, Degeneracy, T)>,
+    ) -> Result<(), &'static str> {
+        // The sampling of SEM routinely leaves a small component with too few rows,
+        // it is reseeded instead of failing
+        let policy = match (self.algorithm, self.degenerate_policy) {
+            (EmAlgorithm::SEM, DegeneratePolicy::Fail) => DegeneratePolicy::Reseed,
+            (_, policy) => policy,
+        };
+
+        degenerate.iter().for_each(|(component, kind, nk)| {
+            self.diagnostics.push(ComponentDiagnostic {
+                component: *component,
+                step: self.steps,
+                kind: *kind,
+                nk: *nk,
+                action: policy,
+            })
+        });
 
-        let curr_z = init.final_z;
+        match policy {
+            DegeneratePolicy::Fail => Err("Degenerate component"),
+            DegeneratePolicy::Reseed => {
+                let mut rng =
+                    rand_chacha::ChaCha8Rng::seed_from_u64(self.seed.wrapping_add(self.steps));
+                let pooled: Matrix<T> = weighted_covariance(
+                    data,
+                    &Partition::single(data.rows),
+                    0,
+                    weights,
+                    WeightKind::Frequency,
+                );
+
+                for (component, _, _) in degenerate {
+                    let point = rng.gen_range(0..data.rows);
+                    means[component].copy_from_slice(&data[point]);
+                    covs[component] = pooled.clone();
+                    mixtures[component] = T::one() / T::from(self.k).unwrap();
+                }
+
+                let total = mixtures.iter().fold(T::zero(), |acc, m| acc + *m);
+                mixtures.iter_mut().for_each(|m| *m = *m / total);
+
+                Ok(())
+            }
+            DegeneratePolicy::Drop => {
+                // Remove from the end so the indices stay valid
+                for (component, _, _) in degenerate.into_iter().rev() {
+                    means
+                        .remove(component, 0)
+                        .expect("Unable to remove a component");
+                    covs.remove(component);
+                    mixtures.remove(component);
+                    self.k -= 1;
+                }
 
+                if self.k == 0 {
+                    return Err("All components are degenerate");
+                }
+
+                let total = mixtures.iter().fold(T::zero(), |acc, m| acc + *m);
+                mixtures.iter_mut().for_each(|m| *m = *m / total);
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Draws rows from the fitted gaussian components
+    ///
+    /// Returns the rows and the component each one was drawn from.
+    pub fn sample(&self, rows: usize, seed: u64) -> Result<(Matrix<T>, Vec<usize>), &'static str> {
+        if self.means.is_empty() {
+            return Err("The model has not been fitted");
+        }
+
+        if self.noise.is_some() {
+            return Err("Cannot sample from the noise component");
+        }
+
+        let lowers: Vec<Matrix<T>> = self
+            .covariance_matrices
+            .iter()
+            .map(cholesky_decomposition)
+            .collect::<Result<_, _>>()
+            .map_err(|_| "Covariance matrix is not positive definite")?;
+
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+        let p = self.means.columns;
+        let mut samples: Matrix<T> = Matrix::zeroes(rows, p);
+        let mut components: Vec<usize> = Vec::with_capacity(rows);
+
+        for row in 0..rows {
+            let draw: T = T::from(rng.gen::<f64>()).unwrap();
+            let mut cumulated: T = T::zero();
+            let component = (0..self.k)
+                .find(|i| {
+                    cumulated = cumulated + self.mixtures[*i];
+                    draw < cumulated
+                })
+                .unwrap_or(self.k - 1);
+
+            // Box-Muller transform
+            let normals: Vec<T> = (0..p)
+                .map(|_| {
+                    let u: f64 = 1.0 - rng.gen::<f64>();
+                    let v: f64 = rng.gen::<f64>();
+                    T::from((-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos())
+                        .unwrap()
+                })
+                .collect();
+
+            (0..p).for_each(|i| {
+                samples[row][i] = (0..=i).fold(self.means[component][i], |acc, j| {
+                    acc + lowers[component][i][j] * normals[j]
+                })
+            });
+            components.push(component);
+        }
+
+        Ok((samples, components))
+    }
+
+    /// Returns the probability of each component for each data point
+    ///
+    /// When there is a noise component, its probabilities are in the last row.
+    pub fn predict_proba(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        if self.means.is_empty() {
+            return Err("The model has not been fitted");
+        }
+
+        let (gammas, _) = self.e_step(data)?;
+        Ok(gammas)
+    }
+
+    /// Stores the responsibilities, splitting off the noise membership if any
+    fn store_responsibilities(&mut self, gammas: Matrix<T>) {
+        match &mut self.noise {
+            Some(noise) => {
+                noise.membership = gammas[self.k].to_vec();
+                noise.proportion = self.mixtures[self.k];
+                self.gammas = Matrix::from_1d_vector(
+                    gammas.content[..self.k * gammas.columns].to_vec(),
+                    self.k,
+                    gammas.columns,
+                );
+            }
+            None => self.gammas = gammas,
+        }
+    }
+
+    /// Clusters the components start from, with the centre of each cluster
+    fn initial_clusters(
+        &self,
+        data: &Matrix<T>,
+        weights: &[T],
+        distance: &dyn distances::Distance<T>,
+    ) -> Result<(Vec<Vec<T>>, Partition), &'static str> {
+        match self.initialization {
+            Initialization::Kmeans => {
+                let mut init = kmeans::Kmeans::init_with_trials(
+                    self.k,
+                    data,
+                    Some(weights),
+                    self.seed,
+                    distance,
+                    1,
+                )?;
+                init.fit(data, Some(weights))?;
+
+                Ok((init.centroids, init.partition))
+            }
+            Initialization::Kmedoids => {
+                let mut init = kmedoids::Kmedoids::new(self.k, self.seed);
+                init.fit(data, distance, Some(weights))?;
+
+                Ok((init.medoid_rows(data), init.partition))
+            }
+        }
+    }
+
+    /// Builds the initial assignments when some labels are known
+    ///
+    /// Components with labelled rows start from their mean, the others from the
+    /// Kmeans centroid with the same index. Unlabelled rows go to the closest start.
+    fn labelled_assignments(
+        data: &Matrix<T>,
+        weights: &[T],
+        centroids: &[Vec<T>],
+        labels: &[Option<usize>],
+        distance: &dyn distances::Distance<T>,
+    ) -> Result<Partition, &'static str> {
+        let k = centroids.len();
+        let known: Vec<usize> = labels.iter().map(|label| label.unwrap_or(k)).collect();
+        // The unlabelled rows are kept in an extra cluster
+        let known =
+            Partition::new(known, k + 1).expect("A label is larger than the number of components");
+
+        let starts: Vec<Vec<T>> = (0..k)
+            .map(|component| match known.sizes()[component] > 0 {
+                true => weighted_means_zs(data, &known, component, weights).unwrap(),
+                false => centroids[component].clone(),
+            })
+            .collect();
+
+        let assignments: Vec<usize> = labels
+            .iter()
+            .enumerate()
+            .map(|(row, label)| match label {
+                Some(label) => Ok(*label),
+                None => {
+                    let distances: Vec<T> = starts
+                        .iter()
+                        .map(|start| distance.distance(&data[row], start))
+                        .collect::<Result<Vec<T>, &'static str>>()?;
+
+                    Ok((0..k).fold(0, |closest, component| {
+                        if distances[component] < distances[closest] {
+                            component
+                        } else {
+                            closest
+                        }
+                    }))
+                }
+            })
+            .collect::<Result<Vec<usize>, &'static str>>()?;
+
+        Partition::new(assignments, k)
+    }
+
+    /// Sets the starting means and covariances from hard assignments
+    ///
+    /// Components left degenerate by the assignments go through the degenerate policy.
+    fn initialise_components(
+        &mut self,
+        data: &Matrix<T>,
+        weights: &[T],
+        assignments: &Partition,
+    ) -> Result<(), &'static str> {
         let mut covariances: Vec<Matrix<T>> = Vec::new();
         let mut means: Matrix<T> = Matrix::empty();
 
-        for z in curr_z {
-            let cov: Matrix<T> = covariance(data, &z);
+        for cluster in 0..assignments.k() {
+            let cov: Matrix<T> =
+                weighted_covariance(data, assignments, cluster, weights, WeightKind::Frequency);
             covariances.push(cov);
-            let m = means_zs(data, &z).unwrap();
+            let m = weighted_means_zs(data, assignments, cluster, weights).unwrap();
             means.append_vector(&m, 0).expect("Unable to append row");
         }
 
         self.means = means;
         self.covariance_matrices = covariances;
+        self.diagnostics = Vec::new();
+        self.online = None;
+
+        let total_weight = weights.iter().fold(T::zero(), |acc, w| acc + *w);
+        let mut counts: Vec<T> = assignments.weighted_sizes(weights);
+
+        // Kmeans can leave a cluster without points
+        let degenerate: Vec<(usize, Degeneracy, T)> = (0..self.k)
+            .filter_map(|i| {
+                Self::degeneracy(
+                    counts[i],
+                    &self.means[i],
+                    &self.covariance_matrices[i],
+                    data.rows,
+                )
+                .map(|kind| (i, kind, counts[i]))
+            })
+            .collect();
+
+        if !degenerate.is_empty() {
+            let mut means = self.means.clone();
+            let mut covs = self.covariance_matrices.clone();
+            let mut mixtures = self.mixtures.clone();
+            let components: Vec<usize> = degenerate.iter().map(|(i, _, _)| *i).collect();
+
+            self.recover(
+                data,
+                weights,
+                &mut means,
+                &mut covs,
+                &mut mixtures,
+                degenerate,
+            )?;
+
+            components
+                .into_iter()
+                .rev()
+                .for_each(|i| match self.degenerate_policy {
+                    DegeneratePolicy::Drop => {
+                        counts.remove(i);
+                    }
+                    _ => counts[i] = total_weight / T::from(self.k).unwrap(),
+                });
+
+            self.means = means;
+            self.covariance_matrices = covs;
+            self.mixtures = mixtures;
+        }
+
+        self.covariance_matrices =
+            self.constrain_covariances(self.covariance_matrices.clone(), &counts);
+
+        Ok(())
+    }
+
+    /// Fits the Gaussian Mixture Model to the data using EM
+    ///
+    /// Rows whose component is known can be given through `labels`, their
+    /// responsibilities stay fixed during EM. Each row can also be given a
+    /// weight, integer weights act as repeated rows.
+    pub fn fit(
+        &mut self,
+        data: &Matrix<T>,
+        distance: &dyn distances::Distance<T>,
+        labels: Option<&[Option<usize>]>,
+        weights: Option<&[T]>,
+    ) -> Result<&'static str, &'static str> {
+        let weights: Vec<T> = match weights {
+            Some(weights) => {
+                if weights.len() != data.rows {
+                    return Err("The weights do not match the number of rows");
+                }
+
+                if weights.iter().any(|w| !w.is_finite() || *w < T::zero()) {
+                    return Err("The weights have to be positive");
+                }
+
+                weights.to_vec()
+            }
+            None => vec![T::one(); data.rows],
+        };
+        let total_weight = weights.iter().fold(T::zero(), |acc, w| acc + *w);
+
+        if let Some(labels) = labels {
+            if labels.len() != data.rows {
+                return Err("The labels do not match the number of rows");
+            }
+
+            if labels.iter().flatten().any(|label| *label >= self.k) {
+                return Err("A label is larger than the number of components");
+            }
+
+            if self.degenerate_policy == DegeneratePolicy::Drop {
+                return Err("Components cannot be dropped when labels are known");
+            }
+        }
+
+        // Rows initially labelled as noise do not take part in the initialisation
+        let signal_rows: Vec<usize> = match &self.noise {
+            Some(noise) => {
+                if noise.initial_noise.len() != data.rows {
+                    return Err("The initial noise labels do not match the number of rows");
+                }
+
+                (0..data.rows)
+                    .filter(|row| !noise.initial_noise[*row])
+                    .collect()
+            }
+            None => (0..data.rows).collect(),
+        };
+
+        let mut signal: Matrix<T> = Matrix::empty();
+        signal_rows.iter().for_each(|row| {
+            signal
+                .append_vector(&data[*row], 0)
+                .expect("Unable to append row");
+        });
+        let signal_weights: Vec<T> = signal_rows.iter().map(|row| weights[*row]).collect();
+        let signal_weight = signal_weights.iter().fold(T::zero(), |acc, w| acc + *w);
+
+        if signal.rows < self.k {
+            return Err("Not enough rows outside of the noise to initialise the components");
+        }
+
+        let (centres, partition) = self.initial_clusters(&signal, &signal_weights, distance)?;
+
+        let curr_z = match labels {
+            Some(labels) => {
+                let signal_labels: Vec<Option<usize>> =
+                    signal_rows.iter().map(|row| labels[*row]).collect();
+                Self::labelled_assignments(
+                    &signal,
+                    &signal_weights,
+                    &centres,
+                    &signal_labels,
+                    distance,
+                )?
+            }
+            None => partition,
+        };
+
+        if let Some(noise) = &self.noise {
+            if self.mixtures.len() == self.k {
+                let proportion = (total_weight - signal_weight) / total_weight;
+                let proportion = proportion.max(T::epsilon());
+
+                self.mixtures
+                    .iter_mut()
+                    .for_each(|m| *m = *m * (T::one() - proportion));
+                self.mixtures.push(proportion);
+            }
+
+            if !noise.hypervolume.is_finite() || noise.hypervolume <= T::zero() {
+                return Err("The noise hypervolume has to be positive");
+            }
+        }
+
+        self.initialise_components(&signal, &signal_weights, &curr_z)?;
 
         let mut counter = 0;
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(self.seed);
+        let mut best: Option<BestStep<T>> = None;
 
         // EM Loop
         loop {
-            let mut gammas: Matrix<T> = Matrix::zeroes(self.k, data.rows);
-
             // E step
-            for i in 0..self.k {
-                let tmp_gammas =
-                    slow_multivariate_gaussian(data, &self.covariance_matrices[i], &self.means[i])
-                        .unwrap();
-                tmp_gammas
-                    .iter()
-                    .enumerate()
-                    .for_each(|(idx, gamma)| gammas[i][idx] = *gamma * self.mixtures[i]);
+            let (mut gammas, mut log_likelihood) = self.weighted_e_step(data, &weights)?;
+
+            if let Some(labels) = labels {
+                log_likelihood = log_likelihood + fix_known_labels(&mut gammas, labels, &weights);
             }
 
-            let sum_gammas_0 = gammas.sum(0).unwrap();
+            match self.algorithm {
+                EmAlgorithm::EM => {}
+                EmAlgorithm::CEM => {
+                    let assignments = Self::classify(&gammas);
+                    log_likelihood =
+                        log_likelihood + fix_known_labels(&mut gammas, &assignments, &weights);
+                }
+                EmAlgorithm::SEM => {
+                    let is_best = match &best {
+                        Some(best) => log_likelihood > best.log_likelihood,
+                        None => log_likelihood.is_finite(),
+                    };
+
+                    if is_best {
+                        best = Some(BestStep {
+                            log_likelihood,
+                            k: self.k,
+                            means: self.means.clone(),
+                            covariance_matrices: self.covariance_matrices.clone(),
+                            mixtures: self.mixtures.clone(),
+                        });
+                    }
+
+                    let assignments = Self::sample_assignments(&gammas, &mut rng);
+                    fix_known_labels(&mut gammas, &assignments, &weights);
+                }
+            }
+
+            // Each row counts as many times as its weight in the M step
+            let mut weighted_gammas: Matrix<T> = gammas.clone();
+            (0..weighted_gammas.rows).for_each(|row| {
+                weighted_gammas[row]
+                    .iter_mut()
+                    .zip(&weights)
+                    .for_each(|(gamma, w)| *gamma = *gamma * *w)
+            });
+
+            self.log_likelihood = log_likelihood;
 
             let mut new_means: Matrix<T> = Matrix::zeroes(self.means.rows, self.means.columns);
 
@@ -119,22 +985,16 @@ where
             data.into_iter()
                 .enumerate()
                 .for_each(|(idx_data_point, dp)| {
-                    // Normalize Gammas for data point dp
-                    for i in 0..gammas.rows {
-                        gammas[i][idx_data_point] =
-                            gammas[i][idx_data_point] / sum_gammas_0[0][idx_data_point];
-                    }
-
                     // Update the means
                     (0..new_means.columns).for_each(|col| {
                         (0..new_means.rows).for_each(|row| {
-                            new_means[row][col] =
-                                new_means[row][col] + (dp[col] * gammas[row][idx_data_point]);
+                            new_means[row][col] = new_means[row][col]
+                                + (dp[col] * weighted_gammas[row][idx_data_point]);
                         });
                     });
                 });
 
-            let mut nk = gammas.sum(1).unwrap();
+            let mut nk = weighted_gammas.sum(1).unwrap();
 
             // Normalize the means
             for row in 0..self.k {
@@ -147,13 +1007,376 @@ where
 
             // Covariance calculation
             for i in 0..self.k {
-                let mut cov: Matrix<T> = covariance_no_z(data, &gammas[i], &new_means[i]);
+                let mut cov: Matrix<T> = covariance_no_z(data, &weighted_gammas[i], &new_means[i]);
                 cov.divide_by_scalar(nk[0][i]);
                 covs.push(cov);
             }
 
+            let mut covs = self.constrain_covariances(covs, &nk.content[..self.k]);
+
             counter += 1;
 
+            let degenerate: Vec<(usize, Degeneracy, T)> = (0..self.k)
+                .filter_map(|i| {
+                    Self::degeneracy(nk[0][i], &new_means[i], &covs[i], data.rows)
+                        .map(|kind| (i, kind, nk[0][i]))
+                })
+                .collect();
+
+            if !degenerate.is_empty() {
+                if counter == self.max_steps {
+                    return match (self.algorithm, best) {
+                        (EmAlgorithm::SEM, Some(best)) => {
+                            self.restore_best(data, &weights, labels, best)
+                        }
+                        _ => Err("Did not converge"),
+                    };
+                }
+
+                nk.divide_by_scalar(total_weight);
+                let mut mixtures = nk.content;
+                self.recover(
+                    data,
+                    &weights,
+                    &mut new_means,
+                    &mut covs,
+                    &mut mixtures,
+                    degenerate,
+                )?;
+
+                self.means = new_means;
+                self.covariance_matrices = covs;
+                self.mixtures = mixtures;
+                self.steps += 1;
+                continue;
+            }
+
+            let mean_error = mean_squared_error(&self.means.content, &new_means.content).unwrap();
+
+            if counter == self.max_steps {
+                return match (self.algorithm, best) {
+                    (EmAlgorithm::SEM, Some(best)) => {
+                        self.restore_best(data, &weights, labels, best)
+                    }
+                    _ => Err("Did not converge"),
+                };
+            }
+
+            // The stochastic steps never settle, SEM always runs for max_steps
+            if mean_error < self.tolerance && self.algorithm != EmAlgorithm::SEM {
+                self.store_responsibilities(gammas);
+                self.final_difference = T::to_f32(&mean_error).unwrap();
+                return Ok("Converged");
+            }
+
+            self.final_difference = T::to_f32(&mean_error).unwrap();
+            self.means = new_means;
+            self.covariance_matrices = covs;
+            nk.divide_by_scalar(total_weight);
+            self.mixtures = nk.content;
+            self.steps += 1;
+        }
+    }
+
+    /// Returns the most likely component of each row
+    fn classify(gammas: &Matrix<T>) -> Vec<Option<usize>> {
+        (0..gammas.columns)
+            .map(|col| {
+                Some((0..gammas.rows).fold(0, |best, row| {
+                    if gammas[row][col] > gammas[best][col] {
+                        row
+                    } else {
+                        best
+                    }
+                }))
+            })
+            .collect()
+    }
+
+    /// Draws the component of each row from its responsibilities
+    fn sample_assignments(
+        gammas: &Matrix<T>,
+        rng: &mut rand_chacha::ChaCha8Rng,
+    ) -> Vec<Option<usize>> {
+        (0..gammas.columns)
+            .map(|col| {
+                let draw: T = T::from(rng.gen::<f64>()).unwrap();
+                let mut cumulated: T = T::zero();
+
+                // Rounding can leave the total slightly under 1, the last component takes it
+                let component = (0..gammas.rows)
+                    .find(|row| {
+                        cumulated = cumulated + gammas[*row][col];
+                        draw < cumulated
+                    })
+                    .unwrap_or(gammas.rows - 1);
+
+                Some(component)
+            })
+            .collect()
+    }
+
+    /// Puts back the SEM parameters with the best likelihood and computes their responsibilities
+    fn restore_best(
+        &mut self,
+        data: &Matrix<T>,
+        weights: &[T],
+        labels: Option<&[Option<usize>]>,
+        best: BestStep<T>,
+    ) -> Result<&'static str, &'static str> {
+        self.k = best.k;
+        self.means = best.means;
+        self.covariance_matrices = best.covariance_matrices;
+        self.mixtures = best.mixtures;
+
+        let (mut gammas, mut log_likelihood) = self.weighted_e_step(data, weights)?;
+
+        if let Some(labels) = labels {
+            log_likelihood = log_likelihood + fix_known_labels(&mut gammas, labels, weights);
+        }
+
+        self.log_likelihood = log_likelihood;
+        self.store_responsibilities(gammas);
+
+        Ok("Kept the best stochastic EM step")
+    }
+
+    /// Computes the responsibilities and the conditional expectations of the missing entries
+    ///
+    /// The densities only use the observed coordinates of each row, the missing
+    /// ones are replaced by their expectation given the observed ones.
+    fn conditional_e_step(
+        &self,
+        data: &Matrix<T>,
+        patterns: &[MissingPattern],
+    ) -> Result<ConditionalExpectations<T>, &'static str> {
+        let two: T = T::from(2.0).unwrap();
+        let ln_two_pi: T = (two * T::from(std::f64::consts::PI).unwrap()).ln();
+
+        let mut gammas: Matrix<T> = Matrix::zeroes(self.k, data.rows);
+        let mut completed: Vec<Matrix<T>> = vec![data.clone(); self.k];
+        let mut conditional_covariances: Vec<Vec<Matrix<T>>> = Vec::with_capacity(self.k);
+
+        for i in 0..self.k {
+            let cov = &self.covariance_matrices[i];
+            let mean = &self.means[i];
+            let log_mixture = self.mixtures[i].ln();
+
+            let block = |rows: &[usize], cols: &[usize]| {
+                let mut block: Matrix<T> = Matrix::zeroes(rows.len(), cols.len());
+                rows.iter().enumerate().for_each(|(a, row)| {
+                    cols.iter()
+                        .enumerate()
+                        .for_each(|(b, col)| block[a][b] = cov[*row][*col])
+                });
+                block
+            };
+
+            let mut pattern_covariances: Vec<Matrix<T>> = Vec::with_capacity(patterns.len());
+
+            for pattern in patterns {
+                let (observed, missing) = (&pattern.observed, &pattern.missing);
+                let mut conditional = block(missing, missing);
+
+                // Nothing observed, the row follows the component marginal
+                if observed.is_empty() {
+                    pattern.rows.iter().for_each(|row| {
+                        gammas[i][*row] = log_mixture;
+                        missing
+                            .iter()
+                            .for_each(|col| completed[i][*row][*col] = mean[*col]);
+                    });
+                    pattern_covariances.push(conditional);
+                    continue;
+                }
+
+                let lower = cholesky_decomposition(&block(observed, observed))
+                    .map_err(|_| "Covariance matrix is not positive definite")?;
+                let constant =
+                    T::from(observed.len()).unwrap() * ln_two_pi + cholesky_log_determinant(&lower);
+
+                // Regression coefficients of each missing coordinate on the observed ones
+                let coefficients: Vec<Vec<T>> = missing
+                    .iter()
+                    .map(|col| {
+                        let cross: Vec<T> = observed.iter().map(|obs| cov[*obs][*col]).collect();
+                        let solved = forward_substitution(&lower, &cross).unwrap();
+                        backward_substitution(&lower, &solved).unwrap()
+                    })
+                    .collect();
+
+                (0..missing.len()).for_each(|a| {
+                    (0..missing.len()).for_each(|b| {
+                        let explained = observed
+                            .iter()
+                            .zip(&coefficients[b])
+                            .fold(T::zero(), |acc, (obs, c)| acc + cov[missing[a]][*obs] * *c);
+                        conditional[a][b] = conditional[a][b] - explained;
+                    })
+                });
+
+                pattern.rows.iter().for_each(|row| {
+                    let centered: Vec<T> = observed
+                        .iter()
+                        .map(|col| data[*row][*col] - mean[*col])
+                        .collect();
+                    let solved = forward_substitution(&lower, &centered).unwrap();
+                    let mahalanobis = dot_product(&solved, &solved).unwrap();
+
+                    gammas[i][*row] = log_mixture - (constant + mahalanobis) / two;
+
+                    missing
+                        .iter()
+                        .zip(&coefficients)
+                        .for_each(|(col, coefficient)| {
+                            completed[i][*row][*col] =
+                                mean[*col] + dot_product(coefficient, &centered).unwrap()
+                        });
+                });
+
+                pattern_covariances.push(conditional);
+            }
+
+            conditional_covariances.push(pattern_covariances);
+        }
+
+        let log_likelihood = normalize_log_densities(&mut gammas);
+
+        Ok(ConditionalExpectations {
+            gammas,
+            log_likelihood,
+            completed,
+            conditional_covariances,
+        })
+    }
+
+    /// Fits the Gaussian Mixture Model to data containing missing entries (NaN)
+    ///
+    /// The entries are assumed missing at random. The E step works on the observed
+    /// coordinates and the M step on the conditional expectations of the missing ones.
+    /// Only the plain EM is supported, every row counts once and none is labelled.
+    pub fn fit_missing(
+        &mut self,
+        data: &Matrix<T>,
+        distance: &dyn distances::Distance<T>,
+    ) -> Result<&'static str, &'static str> {
+        if self.noise.is_some() {
+            return Err("The noise component is not supported with missing values");
+        }
+
+        if self.algorithm != EmAlgorithm::EM {
+            return Err("Only the EM algorithm is supported with missing values");
+        }
+
+        let patterns = MissingPattern::from_data(data);
+
+        // The initialisation runs on the data completed with the column means
+        let mut filled: Matrix<T> = data.clone();
+
+        for col in 0..data.columns {
+            let observed: Vec<T> = (0..data.rows)
+                .map(|row| data[row][col])
+                .filter(|val| !val.is_nan())
+                .collect();
+
+            if observed.is_empty() {
+                return Err("A column has no observed value");
+            }
+
+            let mean = observed.iter().fold(T::zero(), |acc, val| acc + *val)
+                / T::from(observed.len()).unwrap();
+            (0..data.rows)
+                .filter(|row| data[*row][col].is_nan())
+                .for_each(|row| filled[row][col] = mean);
+        }
+
+        let ones = vec![T::one(); data.rows];
+        let (_, partition) = self.initial_clusters(&filled, &ones, distance)?;
+        self.initialise_components(&filled, &ones, &partition)?;
+
+        let mut counter = 0;
+
+        // EM Loop
+        loop {
+            // E step
+            let expectations = self.conditional_e_step(data, &patterns)?;
+            let gammas = expectations.gammas;
+            self.log_likelihood = expectations.log_likelihood;
+
+            // M step
+            let mut nk = gammas.sum(1).unwrap();
+            let mut new_means: Matrix<T> = Matrix::zeroes(self.k, data.columns);
+            let mut covs: Vec<Matrix<T>> = Vec::with_capacity(self.k);
+
+            for i in 0..self.k {
+                let completed = &expectations.completed[i];
+
+                (0..data.rows).for_each(|row| {
+                    (0..data.columns).for_each(|col| {
+                        new_means[i][col] = new_means[i][col] + completed[row][col] * gammas[i][row]
+                    })
+                });
+                new_means[i]
+                    .iter_mut()
+                    .for_each(|mean| *mean = *mean / nk[0][i]);
+
+                let mut cov: Matrix<T> = covariance_no_z(completed, &gammas[i], &new_means[i]);
+
+                // The conditional covariances add the uncertainty of the missing entries
+                patterns
+                    .iter()
+                    .zip(&expectations.conditional_covariances[i])
+                    .for_each(|(pattern, conditional)| {
+                        let weight = pattern
+                            .rows
+                            .iter()
+                            .fold(T::zero(), |acc, row| acc + gammas[i][*row]);
+
+                        pattern.missing.iter().enumerate().for_each(|(a, col1)| {
+                            pattern.missing.iter().enumerate().for_each(|(b, col2)| {
+                                cov[*col1][*col2] = cov[*col1][*col2] + weight * conditional[a][b]
+                            })
+                        });
+                    });
+
+                cov.divide_by_scalar(nk[0][i]);
+                covs.push(cov);
+            }
+
+            let mut covs = self.constrain_covariances(covs, &nk.content);
+
+            counter += 1;
+
+            let degenerate: Vec<(usize, Degeneracy, T)> = (0..self.k)
+                .filter_map(|i| {
+                    Self::degeneracy(nk[0][i], &new_means[i], &covs[i], data.rows)
+                        .map(|kind| (i, kind, nk[0][i]))
+                })
+                .collect();
+
+            if !degenerate.is_empty() {
+                if counter == self.max_steps {
+                    return Err("Did not converge");
+                }
+
+                nk.divide_by_scalar(T::from(data.rows).unwrap());
+                let mut mixtures = nk.content;
+                self.recover(
+                    &filled,
+                    &ones,
+                    &mut new_means,
+                    &mut covs,
+                    &mut mixtures,
+                    degenerate,
+                )?;
+
+                self.means = new_means;
+                self.covariance_matrices = covs;
+                self.mixtures = mixtures;
+                self.steps += 1;
+                continue;
+            }
+
             let mean_error = mean_squared_error(&self.means.content, &new_means.content).unwrap();
 
             if counter == self.max_steps {
@@ -173,4 +1396,270 @@ where
             self.steps += 1;
         }
     }
+
+    /// Returns the data with its missing entries (NaN) imputed
+    ///
+    /// Each missing entry is its conditional expectation given the observed
+    /// coordinates, averaged over the components with the responsibilities.
+    pub fn impute(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        if self.means.is_empty() {
+            return Err("The model has not been fitted");
+        }
+
+        if self.noise.is_some() {
+            return Err("The noise component is not supported with missing values");
+        }
+
+        let patterns = MissingPattern::from_data(data);
+        let expectations = self.conditional_e_step(data, &patterns)?;
+        let mut imputed: Matrix<T> = data.clone();
+
+        patterns.iter().for_each(|pattern| {
+            pattern.rows.iter().for_each(|row| {
+                pattern.missing.iter().for_each(|col| {
+                    imputed[*row][*col] = (0..self.k).fold(T::zero(), |acc, i| {
+                        acc + expectations.gammas[i][*row] * expectations.completed[i][*row][*col]
+                    })
+                })
+            })
+        });
+
+        Ok(imputed)
+    }
+    /// Updates the model with a new chunk of rows using stepwise online EM
+    ///
+    /// The sufficient statistics move towards the ones of the chunk with a step
+    /// size of (updates + 2)^-step_decay. The first chunk also initialises the
+    /// components with Kmeans.
+    pub fn partial_fit(
+        &mut self,
+        chunk: &Matrix<T>,
+        distance: &dyn distances::Distance<T>,
+    ) -> Result<(), &'static str> {
+        if self.noise.is_some() {
+            return Err("The noise component is not supported by the online EM");
+        }
+
+        if self.step_decay <= T::from(0.5).unwrap() || self.step_decay > T::one() {
+            return Err("The step decay has to be in (0.5, 1]");
+        }
+
+        if chunk.rows == 0 {
+            return Ok(());
+        }
+
+        let step: T = match &self.online {
+            Some(statistics) => {
+                if chunk.columns != self.means.columns {
+                    return Err("The chunk does not match the number of columns");
+                }
+
+                T::from(statistics.updates + 2)
+                    .unwrap()
+                    .powf(-self.step_decay)
+            }
+            None => {
+                if chunk.rows < self.k {
+                    return Err("The first chunk needs at least one row per component");
+                }
+
+                let ones = vec![T::one(); chunk.rows];
+                let (_, partition) = self.initial_clusters(chunk, &ones, distance)?;
+
+                self.steps = 0;
+                self.initialise_components(chunk, &ones, &partition)?;
+                T::one()
+            }
+        };
+
+        // E step on the chunk only
+        let (gammas, log_likelihood) = self.e_step(chunk)?;
+        self.log_likelihood = log_likelihood;
+
+        let p = chunk.columns;
+        let rows: T = T::from(chunk.rows).unwrap();
+        let mut statistics = self.online.take().unwrap_or(OnlineStatistics {
+            s0: vec![T::zero(); self.k],
+            s1: Matrix::zeroes(self.k, p),
+            s2: vec![Matrix::zeroes(p, p); self.k],
+            updates: 0,
+        });
+
+        let keep = T::one() - step;
+
+        for i in 0..self.k {
+            let s0 = gammas[i].iter().fold(T::zero(), |acc, gamma| acc + *gamma) / rows;
+            statistics.s0[i] = keep * statistics.s0[i] + step * s0;
+
+            (0..p).for_each(|col1| {
+                let s1 = (0..chunk.rows).fold(T::zero(), |acc, row| {
+                    acc + gammas[i][row] * chunk[row][col1]
+                }) / rows;
+                statistics.s1[i][col1] = keep * statistics.s1[i][col1] + step * s1;
+
+                (0..p).for_each(|col2| {
+                    let s2 = (0..chunk.rows).fold(T::zero(), |acc, row| {
+                        acc + gammas[i][row] * chunk[row][col1] * chunk[row][col2]
+                    }) / rows;
+                    statistics.s2[i][col1][col2] = keep * statistics.s2[i][col1][col2] + step * s2;
+                });
+            });
+        }
+
+        statistics.updates += 1;
+
+        // M step from the running statistics
+        let total = statistics.s0.iter().fold(T::zero(), |acc, s0| acc + *s0);
+        let mut new_means: Matrix<T> = Matrix::zeroes(self.k, p);
+        let mut covs: Vec<Matrix<T>> = Vec::with_capacity(self.k);
+
+        for i in 0..self.k {
+            (0..p).for_each(|col| new_means[i][col] = statistics.s1[i][col] / statistics.s0[i]);
+
+            let mut cov: Matrix<T> = Matrix::zeroes(p, p);
+            (0..p).for_each(|col1| {
+                (0..p).for_each(|col2| {
+                    cov[col1][col2] = statistics.s2[i][col1][col2] / statistics.s0[i]
+                        - new_means[i][col1] * new_means[i][col2]
+                })
+            });
+            covs.push(cov);
+        }
+
+        let nk: Vec<T> = statistics.s0.iter().map(|s0| *s0 * rows).collect();
+        let mut covs = self.constrain_covariances(covs, &nk);
+        let mut mixtures: Vec<T> = statistics.s0.iter().map(|s0| *s0 / total).collect();
+
+        let degenerate: Vec<(usize, Degeneracy, T)> = (0..self.k)
+            .filter_map(|i| {
+                Self::degeneracy(nk[i], &new_means[i], &covs[i], chunk.rows)
+                    .map(|kind| (i, kind, nk[i]))
+            })
+            .collect();
+
+        if !degenerate.is_empty() {
+            let ones = vec![T::one(); chunk.rows];
+            self.recover(
+                chunk,
+                &ones,
+                &mut new_means,
+                &mut covs,
+                &mut mixtures,
+                degenerate,
+            )?;
+
+            // The statistics restart from the recovered parameters
+            statistics.s0 = mixtures.clone();
+            statistics.s1 = Matrix::zeroes(self.k, p);
+            statistics.s2 = Vec::with_capacity(self.k);
+
+            for i in 0..self.k {
+                let mut s2: Matrix<T> = Matrix::zeroes(p, p);
+                (0..p).for_each(|col1| {
+                    statistics.s1[i][col1] = mixtures[i] * new_means[i][col1];
+                    (0..p).for_each(|col2| {
+                        s2[col1][col2] = mixtures[i]
+                            * (covs[i][col1][col2] + new_means[i][col1] * new_means[i][col2])
+                    })
+                });
+                statistics.s2.push(s2);
+            }
+        }
+
+        let mean_error = mean_squared_error(&self.means.content, &new_means.content)
+            .unwrap_or_else(|_| T::infinity());
+        self.final_difference = T::to_f32(&mean_error).unwrap();
+
+        self.means = new_means;
+        self.covariance_matrices = covs;
+        self.mixtures = mixtures;
+        self.online = Some(statistics);
+        self.steps += 1;
+        self.store_responsibilities(gammas);
+
+        Ok(())
+    }
+
+    /// Fits the Gaussian Mixture Model with online EM over chunks of rows
+    ///
+    /// Only one chunk has to be in memory at a time, e.g. from `parser::read_chunks`.
+    /// Each chunk is used for a single update, so the last one decides convergence.
+    pub fn fit_online<I>(
+        &mut self,
+        chunks: I,
+        distance: &dyn distances::Distance<T>,
+    ) -> Result<&'static str, &'static str>
+    where
+        I: IntoIterator<Item = Matrix<T>>,
+    {
+        self.online = None;
+
+        for chunk in chunks {
+            self.partial_fit(&chunk, distance)?;
+        }
+
+        if self.online.is_none() {
+            return Err("There are no rows to fit");
+        }
+
+        if T::from(self.final_difference).unwrap() < self.tolerance {
+            Ok("Converged")
+        } else {
+            Ok("Consumed every chunk before converging")
+        }
+    }
+}
+
+impl<
+        T: Float
+            + Div
+            + Mul
+            + Add
+            + Sub<Output = T>
+            + Sum<<T as Mul>::Output>
+            + Copy
+            + Debug
+            + Sync
+            + Send
+            + PartialOrd
+            + 'static,
+    > MixtureModel<T> for GaussianMixtureModel<T>
+where
+    for<'a> &'a T: Sub<&'a T, Output = T>,
+    T: FromStr,
+    Vec<T>: FromIterator<<T as Div>::Output>,
+{
+    fn fit(
+        &mut self,
+        data: &Matrix<T>,
+        distance: &dyn distances::Distance<T>,
+        labels: Option<&[Option<usize>]>,
+        weights: Option<&[T]>,
+    ) -> Result<&'static str, &'static str> {
+        GaussianMixtureModel::fit(self, data, distance, labels, weights)
+    }
+
+    fn predict_proba(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        GaussianMixtureModel::predict_proba(self, data)
+    }
+
+    fn responsibilities(&self) -> &Matrix<T> {
+        &self.gammas
+    }
+
+    fn log_likelihood(&self) -> T {
+        self.log_likelihood
+    }
+
+    fn number_parameters(&self) -> usize {
+        GaussianMixtureModel::number_parameters(self)
+    }
+
+    fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    fn final_difference(&self) -> f32 {
+        self.final_difference
+    }
 }
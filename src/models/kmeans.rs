@@ -1,6 +1,6 @@
 use std::cmp::Ordering;
 use std::fmt::Debug;
-use std::iter::{zip, Sum};
+use std::iter::Sum;
 use std::ops::{Add, Div, Mul, Sub};
 use std::str::FromStr;
 
@@ -10,15 +10,64 @@ use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
 
 use crate::calculations::matrix::Matrix;
+use crate::calculations::partition::Partition;
 
-use super::distances::{self, eucleadian_distance};
+use super::distances::Distance;
+
+/// Algorithm used to assign the rows to the centroids
+///
+/// The accelerated algorithms skip the distances the triangle inequality proves
+/// useless, they end with the same centroids as Lloyd.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KmeansAlgorithm {
+    /// Every distance is computed at every iteration
+    Lloyd,
+    /// Keeps a lower bound per centroid, skips the most distances
+    Elkan,
+    /// Keeps a single lower bound, lighter in memory when there are many centroids
+    Hamerly,
+}
+
+/// What to do with a centroid left without any row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EmptyClusterPolicy {
+    /// The centroid takes the row farthest from its own centroid
+    Relocate,
+    /// The fit stops with an error
+    Fail,
+}
 
 /// Struct containing the data needed for Kmeans
+///
+/// The distance is not saved, a loaded Kmeans returns an error until
+/// `set_distance` is called.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize + Float",
+        deserialize = "T: serde::Deserialize<'de> + Float"
+    ))
+)]
 pub struct Kmeans<T: 'static> {
     pub centroids: Vec<Vec<T>>,
     k: usize,
-    distance: distances::Distance<T>,
-    pub final_z: Vec<Vec<u8>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    distance: Option<Box<dyn Distance<T>>>,
+    /// Cluster of every row after the last fit
+    pub partition: Partition,
+    pub algorithm: KmeansAlgorithm,
+    /// Number of distance computations during the last fit
+    pub distance_calls: usize,
+    pub max_iterations: usize,
+    /// Largest centroid shift, measured with the bound distance, ending the fit
+    #[cfg_attr(feature = "serde", serde(with = "super::persistence::non_finite"))]
+    pub tolerance: T,
+    pub empty_cluster_policy: EmptyClusterPolicy,
+    /// Weighted sum of the distances of the rows to their centroid after every iteration
+    pub inertia_history: Vec<T>,
 }
 
 impl<
@@ -39,127 +88,775 @@ where
     T: FromStr,
     Vec<T>: FromIterator<<T as Div>::Output>,
 {
-    /// Initializes a new Kmeans struct 
+    /// Initializes a new Kmeans struct
     ///
     /// It runs Kmeans++ to find the best starting centroids
     pub fn init(
         k: usize,
         data: &Matrix<T>,
         seed: u64,
-        distance: distances::Distance<T>,
-    ) -> Kmeans<T> {
-        // Build distance matrix based on indices.
-        // We get a vector of (index, distance)
-        let distances: Vec<Vec<(usize, T)>> = (0..data.rows)
-            .into_par_iter()
-            .map(|f| {
-                let mut x: Vec<(usize, T)> =
-                    zip(0..data.rows, (0..data.len()).collect::<Vec<usize>>())
-                        .map(|r| (r.1, eucleadian_distance(&data[f], &data[r.0]).unwrap()))
-                        .collect();
-
-                x.sort_unstable_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
-                x
-            })
-            .collect();
+        distance: &dyn Distance<T>,
+    ) -> Result<Kmeans<T>, &'static str> {
+        Self::init_with_trials(k, data, None, seed, distance, 1)
+    }
+
+    /// Initializes a new Kmeans struct with the greedy Kmeans++
+    ///
+    /// Each new centroid is the best of `trials` candidates drawn with the D² weighting,
+    /// the one lowering the most the total distance of the rows to their closest centroid.
+    /// A single trial is the plain Kmeans++. Only the distance of each row to its closest
+    /// centroid is kept, so the memory grows with the number of rows and not its square.
+    /// Each row can be given a weight, integer weights act as repeated rows and rows
+    /// without weight are never drawn.
+    pub fn init_with_trials(
+        k: usize,
+        data: &Matrix<T>,
+        weights: Option<&[T]>,
+        seed: u64,
+        distance: &dyn Distance<T>,
+        trials: usize,
+    ) -> Result<Kmeans<T>, &'static str> {
+        if data.rows == 0 {
+            return Err("The data has no rows");
+        }
+
+        if k == 0 {
+            return Err("At least one centroid is needed");
+        }
+
+        let weights = Self::row_weights(data, weights)?;
+
+        if weights.iter().any(|w| !w.is_finite() || *w < T::zero()) {
+            return Err("The weights have to be positive");
+        }
+
+        let largest = weights.iter().fold(T::zero(), |acc, w| acc.max(*w));
+
+        if largest <= T::zero() {
+            return Err("At least one row needs a positive weight");
+        }
 
         let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
-        let mut nodes: Vec<usize> = vec![rng.gen_range(0..data.len())];
+        let mut nodes: Vec<usize> = vec![Self::draw_row(&mut rng, &weights, largest)];
+
+        // The rows are drawn with a weight of their distance, the default squared
+        // eucleadian distance gives the D² weighting
+        let distances_to = |node: usize| -> Result<Vec<T>, &'static str> {
+            (0..data.rows)
+                .into_par_iter()
+                .map(|row| distance.distance(&data[row], &data[node]))
+                .collect()
+        };
 
-        if k > 1 {
-            for _ in 1..k {
-                let mut bins: Vec<(usize, usize)> = (0..data.len()).map(|f| (f, 0)).collect();
+        let mut closest: Vec<T> = distances_to(nodes[0])?;
+
+        for _ in 1..k {
+            let total = closest
+                .iter()
+                .zip(&weights)
+                .fold(T::zero(), |acc, (d, w)| acc + *d * *w);
 
-                for node in &mut nodes {
-                    for (ind, obj) in distances[*node].iter().enumerate() {
-                        bins[obj.0].1 += ind;
+            let candidates: Vec<usize> = (0..trials.max(1))
+                .map(|_| {
+                    // Every row left is a copy of a centroid, any of them will do
+                    if total <= T::zero() {
+                        return Self::draw_row(&mut rng, &weights, largest);
                     }
-                }
 
-                bins.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+                    let draw: T = T::from(rng.gen::<f64>()).unwrap() * total;
+                    let mut cumulated: T = T::zero();
+
+                    (0..data.rows)
+                        .find(|row| {
+                            cumulated = cumulated + closest[*row] * weights[*row];
+                            draw < cumulated
+                        })
+                        .unwrap_or_else(|| {
+                            (0..data.rows)
+                                .rev()
+                                .find(|row| closest[*row] * weights[*row] > T::zero())
+                                .unwrap_or(data.rows - 1)
+                        })
+                })
+                .collect();
 
-                nodes.push(bins[0].0)
+            let mut best: Option<(usize, Vec<T>, T)> = None;
+
+            for candidate in candidates {
+                let updated: Vec<T> = distances_to(candidate)?
+                    .iter()
+                    .zip(&closest)
+                    .map(|(new, old)| new.min(*old))
+                    .collect();
+                let potential = updated
+                    .iter()
+                    .zip(&weights)
+                    .fold(T::zero(), |acc, (d, w)| acc + *d * *w);
+
+                best = match best {
+                    Some(best) if best.2 <= potential => Some(best),
+                    _ => Some((candidate, updated, potential)),
+                };
             }
+
+            let (node, updated, _) = best.expect("At least one candidate is drawn");
+
+            nodes.push(node);
+            closest = updated;
         }
 
-        Self {
+        Ok(Self {
             centroids: nodes
                 .iter()
                 .map(|ind| data[ind.to_owned()].to_vec())
                 .collect(),
             k,
-            distance,
-            final_z: Vec::new(),
+            distance: Some(distance.boxed()),
+            partition: Partition::default(),
+            algorithm: KmeansAlgorithm::Lloyd,
+            distance_calls: 0,
+            max_iterations: 300,
+            tolerance: T::zero(),
+            empty_cluster_policy: EmptyClusterPolicy::Relocate,
+            inertia_history: Vec::new(),
+        })
+    }
+
+    /// Draws a row with a probability proportional to its weight
+    ///
+    /// A uniform draw is kept with a probability of its weight over the largest one,
+    /// equal weights never draw twice and give the same row as a uniform draw.
+    fn draw_row(rng: &mut rand_chacha::ChaCha8Rng, weights: &[T], largest: T) -> usize {
+        loop {
+            let row = rng.gen_range(0..weights.len());
+
+            if weights[row] >= largest
+                || T::from(rng.gen::<f64>()).unwrap() * largest < weights[row]
+            {
+                return row;
+            }
         }
     }
 
+    /// Sets the distance, needed after loading a Kmeans
+    pub fn set_distance(&mut self, distance: &dyn Distance<T>) {
+        self.distance = Some(distance.boxed());
+    }
+
+    /// Returns the distance, or an error if it was not set again after loading
+    fn distance(&self) -> Result<&dyn Distance<T>, &'static str> {
+        self.distance
+            .as_deref()
+            .ok_or("The distance is not saved, it has to be set with set_distance")
+    }
+
+    /// Returns the inertia of the last iteration of the fit
+    pub fn inertia(&self) -> Option<T> {
+        self.inertia_history.last().copied()
+    }
+
+    /// Returns the distance of each row (rows) to each centroid (columns)
+    ///
+    /// The distances are the ones of the Kmeans distance, squared for the default one.
+    pub fn transform(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        self.check_columns(data)?;
+
+        let rows: Vec<Vec<T>> = (0..data.rows)
+            .into_par_iter()
+            .map(|row| {
+                self.centroids
+                    .iter()
+                    .map(|centroid| self.distance()?.distance(&data[row], centroid))
+                    .collect::<Result<Vec<T>, &'static str>>()
+            })
+            .collect::<Result<Vec<Vec<T>>, &'static str>>()?;
+
+        Ok(Matrix::from_1d_vector(
+            rows.into_iter().flatten().collect(),
+            data.rows,
+            self.k,
+        ))
+    }
+
+    /// Returns the closest centroid of each row
+    pub fn predict(&self, data: &Matrix<T>) -> Result<Vec<usize>, &'static str> {
+        let distances = self.transform(data)?;
+
+        Ok((0..distances.rows)
+            .map(|row| Self::closest(&distances[row]))
+            .collect())
+    }
+
+    /// Returns the opposite of the inertia of the data, higher is better
+    ///
+    /// Each row can be given a weight, integer weights act as repeated rows.
+    pub fn score(&self, data: &Matrix<T>, weights: Option<&[T]>) -> Result<T, &'static str> {
+        let weights = Self::row_weights(data, weights)?;
+        let distances = self.transform(data)?;
+
+        let inertia = (0..distances.rows).fold(T::zero(), |acc, row| {
+            acc + weights[row] * distances[row][Self::closest(&distances[row])]
+        });
+
+        Ok(-inertia)
+    }
+
+    /// Returns an error if the data cannot be compared with the centroids
+    fn check_columns(&self, data: &Matrix<T>) -> Result<(), &'static str> {
+        if self.centroids.is_empty() {
+            return Err("The model has no centroids");
+        }
+
+        if data.rows > 0 && data.columns != self.centroids[0].len() {
+            return Err("The number of columns does not match the centroids");
+        }
+
+        Ok(())
+    }
+
+    /// Index of the smallest distance, the first one on ties
+    fn closest(distances: &[T]) -> usize {
+        (1..distances.len()).fold(0, |closest, ind| {
+            match distances[ind].partial_cmp(&distances[closest]) {
+                Some(Ordering::Less) => ind,
+                _ => closest,
+            }
+        })
+    }
+
+    /// Returns the weights of the rows, one for each row when none are given
+    fn row_weights(data: &Matrix<T>, weights: Option<&[T]>) -> Result<Vec<T>, &'static str> {
+        let weights: Vec<T> = match weights {
+            Some(weights) => weights.to_vec(),
+            None => vec![T::one(); data.len()],
+        };
+
+        if weights.len() != data.len() {
+            return Err("The weights do not match the number of rows");
+        }
+
+        Ok(weights)
+    }
+
     /// Fits Kmeans to the data
-    pub fn fit(&mut self, data: &Matrix<T>) {
-        let len_data: usize = data.len();
-        let mut z: Vec<Vec<u8>> = (0..self.k)
-            .map(|_| (0..len_data).map(|_| 0).collect())
-            .collect();
+    ///
+    /// Each row can be given a weight, integer weights act as repeated rows.
+    /// The fit stops once no row changes centroid, once no centroid moves by more
+    /// than `tolerance` (measured with the bound distance) or after `max_iterations`.
+    /// The inertia after every iteration is kept in `inertia_history`.
+    pub fn fit(
+        &mut self,
+        data: &Matrix<T>,
+        weights: Option<&[T]>,
+    ) -> Result<&'static str, &'static str> {
+        let weights = Self::row_weights(data, weights)?;
 
-        let mut prev_z: Vec<Vec<u8>> = (0..self.k)
-            .map(|_| (0..len_data).map(|_| 0).collect())
-            .collect();
+        self.distance_calls = 0;
+        self.inertia_history = Vec::new();
+
+        let mut assignments: Vec<usize> = Vec::new();
+        let mut upper: Vec<T> = Vec::new();
+        // Elkan keeps a lower bound per centroid, Hamerly only one on the second closest
+        let mut lower: Vec<Vec<T>> = Vec::new();
+        let mut second: Vec<T> = Vec::new();
+        let mut moved: Vec<T> = Vec::new();
+        // The bounds are computed again from scratch after a relocation
+        let mut exact: bool = true;
 
-        let mut stable = false;
-
-        while !stable {
-            stable = true;
-
-            // Update assignments
-            (0..data.rows).enumerate().for_each(|(ind, point)| {
-                let mut closest = (
-                    0,
-                    (self.distance)(&data[point], &self.centroids[0])
-                        .expect("Kmeans: Error in distance computation"),
-                );
-                (1..self.k).for_each(|ind| {
-                    let tmp_dist = (self.distance)(&data[point], &self.centroids[ind])
-                        .expect("Kmeans: Error in distance computation");
-                    if let Some(Ordering::Less) = tmp_dist.partial_cmp(&closest.1) {
-                        closest = (ind, tmp_dist);
+        for _ in 0..self.max_iterations {
+            let changed = if exact || self.algorithm == KmeansAlgorithm::Lloyd {
+                let closest = self.initial_bounds(data)?;
+                self.distance_calls += data.rows * self.k;
+
+                let changed = assignments.len() != data.rows
+                    || closest
+                        .iter()
+                        .zip(&assignments)
+                        .any(|((nearest, _), assigned)| nearest != assigned);
+
+                assignments = closest.iter().map(|(a, _)| *a).collect();
+                match self.algorithm {
+                    KmeansAlgorithm::Lloyd => {}
+                    KmeansAlgorithm::Elkan => {
+                        upper = closest.iter().map(|(a, d)| d[*a]).collect();
+                        lower = closest.into_iter().map(|(_, d)| d).collect();
                     }
-                });
+                    KmeansAlgorithm::Hamerly => {
+                        upper = closest.iter().map(|(a, d)| d[*a]).collect();
+                        second = closest
+                            .iter()
+                            .map(|(a, d)| self.second_closest(d, *a))
+                            .collect();
+                    }
+                }
+
+                changed
+            } else {
+                let (changed, calls) = match self.algorithm {
+                    KmeansAlgorithm::Elkan => {
+                        self.elkan_step(data, &mut assignments, &mut upper, &mut lower, &moved)?
+                    }
+                    _ => {
+                        self.hamerly_step(data, &mut assignments, &mut upper, &mut second, &moved)?
+                    }
+                };
+                self.distance_calls += calls;
+
+                changed
+            };
+
+            let relocated = self.relocate_empty_clusters(data, &weights, &mut assignments)?;
+            moved = self.update_centroids(data, &weights, &assignments)?;
 
-                z[closest.0][ind] = 1;
+            let inertia = self.compute_inertia(data, &weights, &assignments)?;
+            self.inertia_history.push(inertia);
+            exact = relocated;
+
+            if !relocated && (!changed || moved.iter().all(|m| *m <= self.tolerance)) {
+                self.partition = Partition::new(assignments, self.k)?;
+                return Ok("Converged");
+            }
+        }
+
+        self.partition = Partition::new(assignments, self.k)?;
+        Ok("Reached the maximum number of iterations")
+    }
+
+    /// Fits Kmeans on random batches of rows (Sculley, 2010)
+    ///
+    /// Every iteration draws `batch_size` rows with the seeded generator and moves each
+    /// centroid towards its rows, with a learning rate of the row weight over the total
+    /// weight the centroid has received so far. The fit stops after `max_iterations`
+    /// batches, or once the smoothed batch inertia has not improved for
+    /// `max_no_improvement` batches in a row. The final assignments use every row, a
+    /// centroid left without rows then follows the empty cluster policy.
+    /// The smoothed batch inertia of every iteration is kept in `inertia_history`.
+    pub fn fit_minibatch(
+        &mut self,
+        data: &Matrix<T>,
+        weights: Option<&[T]>,
+        batch_size: usize,
+        max_iterations: usize,
+        max_no_improvement: usize,
+        seed: u64,
+    ) -> Result<&'static str, &'static str> {
+        let weights = Self::row_weights(data, weights)?;
+        let len_data: usize = data.len();
+
+        if len_data == 0 {
+            return Err("The data has no rows");
+        }
+
+        if batch_size == 0 {
+            return Err("The batch size has to be positive");
+        }
+
+        self.distance_calls = 0;
+        self.inertia_history = Vec::new();
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+        let mut counts: Vec<T> = vec![T::zero(); self.k];
 
-                if prev_z[closest.0][ind] == 0u8 && stable {
-                    stable = false;
+        // The batch inertia is noisy, it is smoothed with an exponential average
+        let smoothing = T::from(2.0 * batch_size as f64 / (len_data as f64 + 1.0))
+            .unwrap()
+            .min(T::one());
+        let mut best = T::infinity();
+        let mut no_improvement: usize = 0;
+        let mut status = "Reached the maximum number of iterations";
+
+        for _ in 0..max_iterations {
+            let batch: Vec<usize> = (0..batch_size)
+                .map(|_| rng.gen_range(0..len_data))
+                .collect();
+
+            let closest: Vec<(usize, T)> = batch
+                .par_iter()
+                .map(|row| {
+                    let (closest, distances) = self.closest_centroid(&data[*row])?;
+                    Ok((closest, distances[closest]))
+                })
+                .collect::<Result<Vec<(usize, T)>, &'static str>>()?;
+            self.distance_calls += batch_size * self.k;
+
+            let total_weight = batch.iter().fold(T::zero(), |acc, row| acc + weights[*row]);
+            let inertia = batch
+                .iter()
+                .zip(&closest)
+                .fold(T::zero(), |acc, (row, (_, d))| acc + weights[*row] * *d)
+                / total_weight;
+
+            batch.iter().zip(&closest).for_each(|(row, (centroid, _))| {
+                if weights[*row] > T::zero() {
+                    counts[*centroid] = counts[*centroid] + weights[*row];
+                    let rate = weights[*row] / counts[*centroid];
+
+                    self.centroids[*centroid]
+                        .iter_mut()
+                        .zip(&data[*row])
+                        .for_each(|(c, x)| *c = *c + rate * (*x - *c));
                 }
             });
 
-            // Update centroids coordinates
-            z.iter().enumerate().for_each(|(ind, centroid_z)| {
+            let current = match self.inertia_history.last() {
+                Some(previous) => *previous * (T::one() - smoothing) + inertia * smoothing,
+                None => inertia,
+            };
+            self.inertia_history.push(current);
+
+            if current < best {
+                best = current;
+                no_improvement = 0;
+            } else {
+                no_improvement += 1;
+
+                if no_improvement >= max_no_improvement {
+                    status = "Converged";
+                    break;
+                }
+            }
+        }
+
+        let mut assignments: Vec<usize> = (0..data.rows)
+            .into_par_iter()
+            .map(|row| Ok(self.closest_centroid(&data[row])?.0))
+            .collect::<Result<Vec<usize>, &'static str>>()?;
+        self.distance_calls += data.rows * self.k;
+
+        // A centroid that never won a row goes through the empty cluster policy,
+        // a relocated centroid is moved onto the row it takes
+        let closest = assignments.clone();
+        if self.relocate_empty_clusters(data, &weights, &mut assignments)? {
+            closest
+                .iter()
+                .zip(&assignments)
+                .enumerate()
+                .filter(|(_, (before, after))| before != after)
+                .for_each(|(row, (_, after))| self.centroids[*after] = data[row].to_vec());
+        }
+
+        self.partition = Partition::new(assignments, self.k)?;
+
+        Ok(status)
+    }
+
+    /// Weighted sum of the distances of the rows to their centroid
+    fn compute_inertia(
+        &mut self,
+        data: &Matrix<T>,
+        weights: &[T],
+        assignments: &[usize],
+    ) -> Result<T, &'static str> {
+        self.distance_calls += data.rows;
+
+        Ok((0..data.rows)
+            .into_par_iter()
+            .map(|row| {
+                let distance = self
+                    .distance()?
+                    .distance(&data[row], &self.centroids[assignments[row]])?;
+                Ok(weights[row] * distance)
+            })
+            .collect::<Result<Vec<T>, &'static str>>()?
+            .iter()
+            .fold(T::zero(), |acc, d| acc + *d))
+    }
+
+    /// Gives a row to every centroid left without any weight
+    ///
+    /// With the relocate policy each empty centroid takes the row farthest from its
+    /// own centroid, among the clusters that keep other rows. Returns whether any
+    /// row was moved.
+    fn relocate_empty_clusters(
+        &mut self,
+        data: &Matrix<T>,
+        weights: &[T],
+        assignments: &mut [usize],
+    ) -> Result<bool, &'static str> {
+        let mut totals: Vec<T> = vec![T::zero(); self.k];
+        assignments
+            .iter()
+            .zip(weights)
+            .for_each(|(assigned, weight)| totals[*assigned] = totals[*assigned] + *weight);
+
+        let empty: Vec<usize> = (0..self.k)
+            .filter(|centroid| totals[*centroid] <= T::zero())
+            .collect();
+
+        if empty.is_empty() {
+            return Ok(false);
+        }
+
+        if self.empty_cluster_policy == EmptyClusterPolicy::Fail {
+            return Err("A centroid has no rows assigned");
+        }
+
+        let mut farthest: Vec<(usize, T)> = (0..data.rows)
+            .into_par_iter()
+            .map(|row| {
+                let distance = self
+                    .distance()?
+                    .distance(&data[row], &self.centroids[assignments[row]])?;
+                Ok((row, distance))
+            })
+            .collect::<Result<Vec<(usize, T)>, &'static str>>()?;
+        self.distance_calls += data.rows;
+        farthest.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+
+        let mut candidates = farthest
+            .into_iter()
+            .map(|(row, _)| row)
+            .filter(|row| weights[*row] > T::zero());
+
+        for centroid in empty {
+            let row = candidates
+                .find(|row| totals[assignments[*row]] > weights[*row])
+                .ok_or("Not enough rows to fill the empty centroids")?;
+
+            totals[assignments[row]] = totals[assignments[row]] - weights[row];
+            totals[centroid] = weights[row];
+            assignments[row] = centroid;
+        }
+
+        Ok(true)
+    }
+
+    /// Distance used by the bounds of the accelerated algorithms
+    ///
+    /// The square root of the squared eucleadian distance, or of any metric,
+    /// satisfies the triangle inequality and keeps the same closest centroid.
+    fn bound_distance(&self, a: &[T], b: &[T]) -> Result<T, &'static str> {
+        Ok(self.distance()?.distance(a, b)?.sqrt())
+    }
+
+    /// Moves every centroid to the weighted mean of its rows
+    ///
+    /// Returns how far each centroid moved, measured with the bound distance.
+    fn update_centroids(
+        &mut self,
+        data: &Matrix<T>,
+        weights: &[T],
+        assignments: &[usize],
+    ) -> Result<Vec<T>, &'static str> {
+        (0..self.k)
+            .map(|centroid| {
                 let mut total: T = T::zero();
-                let mut new_coords: Vec<T> = (0..self.centroids[0].len())
-                    .map(|_| T::from(0.0).unwrap())
-                    .collect();
+                let mut new_coords: Vec<T> = vec![T::zero(); self.centroids[centroid].len()];
 
-                centroid_z.iter().enumerate().for_each(|(ind, val)| {
-                    if val == &1 {
-                        total = total + T::one();
-                        data[ind]
-                            .iter()
-                            .enumerate()
-                            .for_each(|(i, attr)| new_coords[i] = new_coords[i] + *attr);
+                assignments.iter().enumerate().for_each(|(row, assigned)| {
+                    if *assigned == centroid {
+                        total = total + weights[row];
+                        data[row].iter().enumerate().for_each(|(i, attr)| {
+                            new_coords[i] = new_coords[i] + *attr * weights[row]
+                        });
                     }
                 });
 
-                self.centroids[ind] = new_coords
-                    .iter()
-                    .map(|val| val.to_owned() / total)
-                    .collect::<Vec<T>>();
+                let new_coords: Vec<T> = new_coords.iter().map(|val| *val / total).collect();
+                let moved = self.bound_distance(&self.centroids[centroid], &new_coords)?;
+                self.centroids[centroid] = new_coords;
+
+                Ok(moved)
+            })
+            .collect()
+    }
+
+    /// Returns the closest centroid of a row and the distance of every centroid
+    fn closest_centroid(&self, row: &[T]) -> Result<(usize, Vec<T>), &'static str> {
+        let distances: Vec<T> = self
+            .centroids
+            .iter()
+            .map(|centroid| self.distance()?.distance(row, centroid))
+            .collect::<Result<Vec<T>, &'static str>>()?;
+
+        Ok((Self::closest(&distances), distances))
+    }
+
+    /// Computes the assignments and the bound distance of every centroid for every row
+    fn initial_bounds(&self, data: &Matrix<T>) -> Result<Vec<(usize, Vec<T>)>, &'static str> {
+        (0..data.rows)
+            .into_par_iter()
+            .map(|row| {
+                let (closest, distances) = self.closest_centroid(&data[row])?;
+                Ok((closest, distances.iter().map(|d| d.sqrt()).collect()))
+            })
+            .collect()
+    }
+
+    /// Distance to the second closest centroid
+    fn second_closest(&self, distances: &[T], closest: usize) -> T {
+        (0..self.k)
+            .filter(|centroid| *centroid != closest)
+            .fold(T::infinity(), |acc, centroid| acc.min(distances[centroid]))
+    }
+
+    /// Returns half the distance between every pair of centroids and, for each
+    /// centroid, half the distance to its closest other centroid
+    fn centroid_separations(&self) -> Result<(Vec<Vec<T>>, Vec<T>), &'static str> {
+        let two = T::from(2.0).unwrap();
+        let half: Vec<Vec<T>> = (0..self.k)
+            .map(|a| {
+                (0..self.k)
+                    .map(|b| Ok(self.bound_distance(&self.centroids[a], &self.centroids[b])? / two))
+                    .collect()
+            })
+            .collect::<Result<Vec<Vec<T>>, &'static str>>()?;
+
+        let closest: Vec<T> = (0..self.k)
+            .map(|a| {
+                (0..self.k)
+                    .filter(|b| *b != a)
+                    .fold(T::infinity(), |acc, b| acc.min(half[a][b]))
+            })
+            .collect();
+
+        Ok((half, closest))
+    }
+
+    /// One assignment step of Elkan's algorithm, one upper bound and one lower bound
+    /// per centroid for each row
+    ///
+    /// Returns whether any row changed centroid and the number of distances computed.
+    fn elkan_step(
+        &self,
+        data: &Matrix<T>,
+        assignments: &mut [usize],
+        upper: &mut [T],
+        lower: &mut [Vec<T>],
+        moved: &[T],
+    ) -> Result<(bool, usize), &'static str> {
+        assignments
+            .iter()
+            .zip(upper.iter_mut())
+            .zip(lower.iter_mut())
+            .for_each(|((assigned, upper), lower)| {
+                *upper = *upper + moved[*assigned];
+                lower
+                    .iter_mut()
+                    .zip(moved)
+                    .for_each(|(l, m)| *l = (*l - *m).max(T::zero()));
             });
 
-            prev_z = z;
-            z = (0..self.k)
-                .map(|_| (0..len_data).map(|_| 0).collect())
-                .collect();
-        }
+        let (half, closest) = self.centroid_separations()?;
+
+        let calls: Vec<(bool, usize)> = assignments
+            .par_iter_mut()
+            .zip(upper.par_iter_mut())
+            .zip(lower.par_iter_mut())
+            .enumerate()
+            .map(|(row, ((assigned, upper), lower))| {
+                let mut calls: usize = 0;
+                let before = *assigned;
+
+                if *upper <= closest[*assigned] {
+                    return Ok((false, calls));
+                }
+
+                let mut tight = false;
+                for centroid in 0..self.k {
+                    if centroid == *assigned
+                        || *upper <= lower[centroid]
+                        || *upper <= half[*assigned][centroid]
+                    {
+                        continue;
+                    }
+
+                    if !tight {
+                        *upper = self.bound_distance(&data[row], &self.centroids[*assigned])?;
+                        lower[*assigned] = *upper;
+                        calls += 1;
+                        tight = true;
+
+                        if *upper <= lower[centroid] || *upper <= half[*assigned][centroid] {
+                            continue;
+                        }
+                    }
+
+                    let distance = self.bound_distance(&data[row], &self.centroids[centroid])?;
+                    lower[centroid] = distance;
+                    calls += 1;
+
+                    if distance < *upper || distance == *upper && centroid < *assigned {
+                        *assigned = centroid;
+                        *upper = distance;
+                    }
+                }
+
+                Ok((*assigned != before, calls))
+            })
+            .collect::<Result<Vec<(bool, usize)>, &'static str>>()?;
+
+        Ok((
+            calls.iter().any(|(changed, _)| *changed),
+            self.k * self.k + calls.iter().map(|(_, calls)| calls).sum::<usize>(),
+        ))
+    }
+
+    /// One assignment step of Hamerly's algorithm, one upper bound and a single lower
+    /// bound for each row
+    ///
+    /// Returns whether any row changed centroid and the number of distances computed.
+    fn hamerly_step(
+        &self,
+        data: &Matrix<T>,
+        assignments: &mut [usize],
+        upper: &mut [T],
+        lower: &mut [T],
+        moved: &[T],
+    ) -> Result<(bool, usize), &'static str> {
+        // The lower bound moves by the largest shift among the other centroids
+        let largest = (0..self.k).fold(0, |best, c| if moved[c] > moved[best] { c } else { best });
+        let runner_up = (0..self.k)
+            .filter(|c| *c != largest)
+            .fold(T::zero(), |acc, c| acc.max(moved[c]));
+
+        assignments
+            .iter()
+            .zip(upper.iter_mut())
+            .zip(lower.iter_mut())
+            .for_each(|((assigned, upper), lower)| {
+                *upper = *upper + moved[*assigned];
+                let shift = if *assigned == largest {
+                    runner_up
+                } else {
+                    moved[largest]
+                };
+                *lower = *lower - shift;
+            });
+
+        let (_, closest) = self.centroid_separations()?;
+
+        let calls: Vec<(bool, usize)> = assignments
+            .par_iter_mut()
+            .zip(upper.par_iter_mut())
+            .zip(lower.par_iter_mut())
+            .enumerate()
+            .map(|(row, ((assigned, upper), lower))| {
+                let bound = closest[*assigned].max(*lower);
+
+                if *upper <= bound {
+                    return Ok((false, 0));
+                }
+
+                *upper = self.bound_distance(&data[row], &self.centroids[*assigned])?;
+
+                if *upper <= bound {
+                    return Ok((false, 1));
+                }
+
+                let before = *assigned;
+                let (nearest, distances) = self.closest_centroid(&data[row])?;
+                let distances: Vec<T> = distances.iter().map(|d| d.sqrt()).collect();
+                *assigned = nearest;
+                *upper = distances[nearest];
+                *lower = self.second_closest(&distances, nearest);
+
+                Ok((*assigned != before, 1 + self.k))
+            })
+            .collect::<Result<Vec<(bool, usize)>, &'static str>>()?;
 
-        self.final_z = prev_z;
+        Ok((
+            calls.iter().any(|(changed, _)| *changed),
+            self.k * self.k + calls.iter().map(|(_, calls)| calls).sum::<usize>(),
+        ))
     }
 }
@@ -0,0 +1,358 @@
+//! K-medoids clustering with PAM and FasterPAM (Schubert and Rousseeuw, 2021)
+//!
+//! The centre of every cluster is one of the rows, the medoid, chosen to minimise
+//! the total distance of the rows to their closest medoid. Only distances between
+//! rows are needed, so any metric can be used and outliers do not drag the centres.
+
+use std::fmt::Debug;
+use std::iter::Sum;
+
+use num::Float;
+use rand::SeedableRng;
+use rayon::prelude::*;
+
+use super::distances::Distance;
+
+use crate::calculations::matrix::Matrix;
+use crate::calculations::partition::Partition;
+
+/// Algorithm used to search the medoids
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KmedoidsAlgorithm {
+    /// Greedy BUILD start, then the best of all the swaps at every pass
+    Pam,
+    /// Random start, then every swap lowering the deviation is applied as soon as it is found
+    FasterPam,
+}
+
+/// Struct containing all the information about the K-medoids clustering
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize + Float",
+        deserialize = "T: serde::Deserialize<'de> + Float"
+    ))
+)]
+pub struct Kmedoids<T> {
+    k: usize,
+    seed: u64,
+    pub algorithm: KmedoidsAlgorithm,
+    /// Largest number of passes over the rows
+    pub max_iterations: usize,
+    /// Row of each medoid
+    pub medoids: Vec<usize>,
+    /// Closest medoid of every row after the last fit
+    pub partition: Partition,
+    /// Weighted sum of the distances of the rows to their medoid
+    #[cfg_attr(feature = "serde", serde(with = "super::persistence::non_finite"))]
+    pub deviation: T,
+    /// Number of swaps performed during the last fit
+    pub swaps: usize,
+}
+
+/// Closest and second closest medoid of a row
+#[derive(Clone, Copy)]
+struct Nearest<T> {
+    medoid: usize,
+    distance: T,
+    second: T,
+}
+
+impl<T: Float + Debug + Send + Sync + Sum + 'static> Kmedoids<T> {
+    /// Returns a new Kmedoids struct
+    pub fn new(k: usize, seed: u64) -> Kmedoids<T> {
+        Self {
+            k,
+            seed,
+            algorithm: KmedoidsAlgorithm::FasterPam,
+            max_iterations: 100,
+            medoids: Vec::new(),
+            partition: Partition::default(),
+            deviation: T::infinity(),
+            swaps: 0,
+        }
+    }
+
+    /// Fits K-medoids to the data
+    ///
+    /// The distances between every pair of rows are computed once, in parallel,
+    /// and stored in a single matrix, so the memory grows with the square of the
+    /// number of rows. Each row can be given a weight, integer weights act as
+    /// repeated rows.
+    pub fn fit(
+        &mut self,
+        data: &Matrix<T>,
+        distance: &dyn Distance<T>,
+        weights: Option<&[T]>,
+    ) -> Result<&'static str, &'static str> {
+        let n = data.rows;
+        let mut dissimilarities: Matrix<T> = Matrix::zeroes(n, n);
+
+        dissimilarities
+            .content
+            .par_chunks_mut(n.max(1))
+            .enumerate()
+            .try_for_each(|(a, row)| {
+                row.iter_mut().enumerate().try_for_each(|(b, d)| {
+                    *d = distance.distance(&data[a], &data[b])?;
+                    Ok(())
+                })
+            })?;
+
+        self.fit_dissimilarities(&dissimilarities, weights)
+    }
+
+    /// Fits K-medoids to a square matrix of distances between the rows
+    pub fn fit_dissimilarities(
+        &mut self,
+        dissimilarities: &Matrix<T>,
+        weights: Option<&[T]>,
+    ) -> Result<&'static str, &'static str> {
+        let n = dissimilarities.rows;
+
+        if dissimilarities.columns != n {
+            return Err("The dissimilarity matrix has to be square");
+        }
+
+        if self.k == 0 || self.k > n {
+            return Err("The number of medoids has to be between 1 and the number of rows");
+        }
+
+        let weights: Vec<T> = match weights {
+            Some(weights) => weights.to_vec(),
+            None => vec![T::one(); n],
+        };
+
+        if weights.len() != n {
+            return Err("The weights do not match the number of rows");
+        }
+
+        if weights.iter().any(|w| !w.is_finite() || *w < T::zero()) {
+            return Err("The weights have to be positive");
+        }
+
+        self.swaps = 0;
+        self.medoids = match self.algorithm {
+            KmedoidsAlgorithm::Pam => Self::build(dissimilarities, &weights, self.k),
+            KmedoidsAlgorithm::FasterPam if self.k == 1 => {
+                Self::build(dissimilarities, &weights, 1)
+            }
+            KmedoidsAlgorithm::FasterPam => {
+                let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(self.seed);
+                rand::seq::index::sample(&mut rng, n, self.k).into_vec()
+            }
+        };
+
+        let mut nearest = self.nearest(dissimilarities);
+        // A single medoid found by BUILD is already the best one
+        let mut status = "Converged";
+
+        if self.k > 1 {
+            status = match self.algorithm {
+                KmedoidsAlgorithm::Pam => self.pam_swaps(dissimilarities, &weights, &mut nearest),
+                KmedoidsAlgorithm::FasterPam => {
+                    self.faster_pam_swaps(dissimilarities, &weights, &mut nearest)
+                }
+            };
+        }
+
+        self.deviation = nearest
+            .iter()
+            .zip(&weights)
+            .fold(T::zero(), |acc, (near, w)| acc + *w * near.distance);
+        self.partition = Partition::new(nearest.iter().map(|near| near.medoid).collect(), self.k)?;
+
+        Ok(status)
+    }
+
+    /// Returns the rows of the medoids, one per cluster
+    pub fn medoid_rows(&self, data: &Matrix<T>) -> Vec<Vec<T>> {
+        self.medoids
+            .iter()
+            .map(|medoid| data[*medoid].to_vec())
+            .collect()
+    }
+
+    /// Greedy start of PAM, each new medoid lowers the deviation the most
+    fn build(dissimilarities: &Matrix<T>, weights: &[T], k: usize) -> Vec<usize> {
+        let n = dissimilarities.rows;
+        let mut medoids: Vec<usize> = Vec::with_capacity(k);
+        let mut closest: Vec<T> = vec![T::infinity(); n];
+
+        for _ in 0..k {
+            let (best, _) = (0..n)
+                .into_par_iter()
+                .filter(|candidate| !medoids.contains(candidate))
+                .map(|candidate| {
+                    let deviation = (0..n).fold(T::zero(), |acc, row| {
+                        acc + weights[row] * closest[row].min(dissimilarities[row][candidate])
+                    });
+                    (candidate, deviation)
+                })
+                .reduce_with(|a, b| {
+                    if b.1 < a.1 || b.1 == a.1 && b.0 < a.0 {
+                        b
+                    } else {
+                        a
+                    }
+                })
+                .expect("There are more rows than medoids");
+
+            medoids.push(best);
+            closest
+                .iter_mut()
+                .enumerate()
+                .for_each(|(row, c)| *c = c.min(dissimilarities[row][best]));
+        }
+
+        medoids
+    }
+
+    /// Closest and second closest medoid of every row
+    fn nearest(&self, dissimilarities: &Matrix<T>) -> Vec<Nearest<T>> {
+        (0..dissimilarities.rows)
+            .map(|row| {
+                let mut near = Nearest {
+                    medoid: 0,
+                    distance: T::infinity(),
+                    second: T::infinity(),
+                };
+
+                self.medoids.iter().enumerate().for_each(|(i, medoid)| {
+                    let d = dissimilarities[row][*medoid];
+                    if d < near.distance {
+                        near.second = near.distance;
+                        near.medoid = i;
+                        near.distance = d;
+                    } else if d < near.second {
+                        near.second = d;
+                    }
+                });
+
+                near
+            })
+            .collect()
+    }
+
+    /// Increase of the deviation when each medoid is removed
+    fn removal_loss(&self, weights: &[T], nearest: &[Nearest<T>]) -> Vec<T> {
+        let mut loss: Vec<T> = vec![T::zero(); self.k];
+        nearest.iter().zip(weights).for_each(|(near, w)| {
+            loss[near.medoid] = loss[near.medoid] + *w * (near.second - near.distance);
+        });
+
+        loss
+    }
+
+    /// Best medoid to replace by the candidate and the change of the deviation
+    fn best_swap(
+        &self,
+        dissimilarities: &Matrix<T>,
+        weights: &[T],
+        nearest: &[Nearest<T>],
+        loss: &[T],
+        candidate: usize,
+    ) -> (usize, T) {
+        let mut delta: Vec<T> = loss.to_vec();
+        let mut shared: T = T::zero();
+
+        nearest.iter().enumerate().for_each(|(row, near)| {
+            let d = dissimilarities[row][candidate];
+
+            if d < near.distance {
+                // The row moves to the candidate whichever medoid is removed
+                shared = shared + weights[row] * (d - near.distance);
+                delta[near.medoid] =
+                    delta[near.medoid] + weights[row] * (near.distance - near.second);
+            } else if d < near.second {
+                delta[near.medoid] = delta[near.medoid] + weights[row] * (d - near.second);
+            }
+        });
+
+        let removed = (1..self.k).fold(0, |best, i| if delta[i] < delta[best] { i } else { best });
+
+        (removed, delta[removed] + shared)
+    }
+
+    /// Applies the best swap of all the candidates at every pass
+    fn pam_swaps(
+        &mut self,
+        dissimilarities: &Matrix<T>,
+        weights: &[T],
+        nearest: &mut Vec<Nearest<T>>,
+    ) -> &'static str {
+        for _ in 0..self.max_iterations {
+            let loss = self.removal_loss(weights, nearest);
+
+            let best = (0..dissimilarities.rows)
+                .into_par_iter()
+                .filter(|candidate| !self.medoids.contains(candidate))
+                .map(|candidate| {
+                    let (removed, change) =
+                        self.best_swap(dissimilarities, weights, nearest, &loss, candidate);
+                    (candidate, removed, change)
+                })
+                .reduce_with(|a, b| {
+                    if b.2 < a.2 || b.2 == a.2 && b.0 < a.0 {
+                        b
+                    } else {
+                        a
+                    }
+                });
+
+            match best {
+                Some((candidate, removed, change)) if change < T::zero() => {
+                    self.medoids[removed] = candidate;
+                    self.swaps += 1;
+                    *nearest = self.nearest(dissimilarities);
+                }
+                _ => return "Converged",
+            }
+        }
+
+        "Reached the maximum number of iterations"
+    }
+
+    /// Applies every swap lowering the deviation, stops after a whole pass without any
+    fn faster_pam_swaps(
+        &mut self,
+        dissimilarities: &Matrix<T>,
+        weights: &[T],
+        nearest: &mut Vec<Nearest<T>>,
+    ) -> &'static str {
+        let n = dissimilarities.rows;
+        let mut loss = self.removal_loss(weights, nearest);
+        let mut last_swap: Option<usize> = None;
+
+        for _ in 0..self.max_iterations {
+            for candidate in 0..n {
+                if last_swap == Some(candidate) {
+                    return "Converged";
+                }
+
+                if self.medoids.contains(&candidate) {
+                    continue;
+                }
+
+                let (removed, change) =
+                    self.best_swap(dissimilarities, weights, nearest, &loss, candidate);
+
+                if change < T::zero() {
+                    self.medoids[removed] = candidate;
+                    self.swaps += 1;
+                    last_swap = Some(candidate);
+                    *nearest = self.nearest(dissimilarities);
+                    loss = self.removal_loss(weights, nearest);
+                }
+            }
+
+            if last_swap.is_none() {
+                return "Converged";
+            }
+        }
+
+        "Reached the maximum number of iterations"
+    }
+}
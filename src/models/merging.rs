@@ -0,0 +1,145 @@
+//! Entropy-based merging of mixture components into clusters (Baudry et al., 2010)
+//!
+//! A cluster that is not gaussian is often described by several components. Starting
+//! from the fitted responsibilities, the two clusters whose merge lowers the entropy
+//! of the soft assignments the most are combined, until a single cluster is left.
+
+use std::fmt::Debug;
+use std::iter::Sum;
+
+use num::Float;
+
+use super::gmm::GaussianMixtureModel;
+
+use crate::calculations::matrix::Matrix;
+
+/// One level of the merge hierarchy
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MergeLevel<T> {
+    /// Components of the fitted model making up each cluster
+    pub clusters: Vec<Vec<usize>>,
+    /// Clusters of the previous level merged into this one, None for the first level
+    pub merged: Option<(usize, usize)>,
+    /// Entropy of the combined responsibilities
+    pub entropy: T,
+    /// Most likely cluster of each data point
+    pub labels: Vec<usize>,
+}
+
+/// Levels of the merge, from one cluster per component down to a single cluster
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MergeHierarchy<T> {
+    pub levels: Vec<MergeLevel<T>>,
+}
+
+impl<T> MergeHierarchy<T> {
+    /// Returns the level with the given number of clusters
+    pub fn level(&self, clusters: usize) -> Option<&MergeLevel<T>> {
+        self.levels
+            .iter()
+            .find(|level| level.clusters.len() == clusters)
+    }
+}
+
+/// Merges the components of a fitted GaussianMixtureModel
+///
+/// The noise component, if any, is left out of the merge.
+pub fn merge_components<T>(gmm: &GaussianMixtureModel<T>) -> Result<MergeHierarchy<T>, &'static str>
+where
+    T: Float + Debug + Send + Sync + Sum + 'static,
+{
+    merge_responsibilities(&gmm.gammas)
+}
+
+/// Merges the components given their responsibilities
+///
+/// Expects the probability of each component (rows) for each data point (columns).
+pub fn merge_responsibilities<T>(
+    responsibilities: &Matrix<T>,
+) -> Result<MergeHierarchy<T>, &'static str>
+where
+    T: Float + Debug + Send + Sync + Sum + 'static,
+{
+    if responsibilities.is_empty() {
+        return Err("The model has not been fitted");
+    }
+
+    let mut tau: Vec<Vec<T>> = (0..responsibilities.rows)
+        .map(|component| responsibilities[component].to_vec())
+        .collect();
+    let mut clusters: Vec<Vec<usize>> = (0..tau.len()).map(|component| vec![component]).collect();
+
+    let mut levels = vec![MergeLevel {
+        clusters: clusters.clone(),
+        merged: None,
+        entropy: entropy(&tau),
+        labels: labels(&tau),
+    }];
+
+    while tau.len() > 1 {
+        // Entropy lost by merging a and b
+        let mut best: (usize, usize, T) = (0, 1, T::neg_infinity());
+        for a in 0..tau.len() {
+            for b in a + 1..tau.len() {
+                let gain = tau[a].iter().zip(&tau[b]).fold(T::zero(), |acc, (ta, tb)| {
+                    acc + x_log_x(*ta + *tb) - x_log_x(*ta) - x_log_x(*tb)
+                });
+
+                if gain > best.2 {
+                    best = (a, b, gain);
+                }
+            }
+        }
+
+        let (a, b, _) = best;
+        let removed = tau.remove(b);
+        tau[a]
+            .iter_mut()
+            .zip(removed)
+            .for_each(|(ta, tb)| *ta = *ta + tb);
+        let removed = clusters.remove(b);
+        clusters[a].extend(removed);
+
+        levels.push(MergeLevel {
+            clusters: clusters.clone(),
+            merged: Some((a, b)),
+            entropy: entropy(&tau),
+            labels: labels(&tau),
+        });
+    }
+
+    Ok(MergeHierarchy { levels })
+}
+
+/// Computes x * ln(x), taken as 0 in 0
+fn x_log_x<T: Float>(x: T) -> T {
+    if x > T::zero() {
+        x * x.ln()
+    } else {
+        T::zero()
+    }
+}
+
+/// Entropy of the soft assignments
+fn entropy<T: Float>(tau: &[Vec<T>]) -> T {
+    -tau.iter()
+        .flatten()
+        .fold(T::zero(), |acc, t| acc + x_log_x(*t))
+}
+
+/// Most likely cluster of each data point
+fn labels<T: Float>(tau: &[Vec<T>]) -> Vec<usize> {
+    (0..tau[0].len())
+        .map(|idx| {
+            (0..tau.len()).fold(0, |best, cluster| {
+                if tau[cluster][idx] > tau[best][idx] {
+                    cluster
+                } else {
+                    best
+                }
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,475 @@
+use std::fmt::Debug;
+use std::iter::Sum;
+use std::ops::{Add, Div, Mul, Sub};
+use std::str::FromStr;
+
+use num::Float;
+use rand::{Rng, SeedableRng};
+
+use super::mixture::MixtureModel;
+use super::{distances, kmeans};
+
+use crate::calculations::linear_algebra::{
+    cholesky_decomposition, cholesky_inverse, cholesky_log_determinant, forward_substitution,
+};
+use crate::calculations::matrix::Matrix;
+use crate::calculations::partition::Partition;
+use crate::calculations::stats::{means_zs, normalize_log_densities};
+use crate::helpers::mean_squared_error;
+
+/// Struct containing all the information about the Mixture of Factor Analyzers
+///
+/// Each covariance is constrained to Λ Λ^T + Ψ with Λ a (p x q) loading matrix and
+/// Ψ a diagonal matrix of uniquenesses. The number of parameters grows as p * q
+/// instead of p² which makes it usable on wide data. The p x p covariances are
+/// never built, the densities rely on the Woodbury identity.
+pub struct MixtureOfFactorAnalyzers<T> {
+    means: Matrix<T>,
+    pub loadings: Vec<Matrix<T>>,
+    pub uniquenesses: Vec<Vec<T>>,
+    k: usize,
+    factors: usize,
+    seed: u64,
+    mixtures: Vec<T>,
+    tolerance: T,
+    pub final_difference: f32,
+    max_steps: i32,
+    pub steps: u64,
+    pub gammas: Matrix<T>,
+    pub log_likelihood: T,
+    pub shared_uniquenesses: bool,
+}
+
+impl<
+        T: Float
+            + Div
+            + Mul
+            + Add
+            + Sub<Output = T>
+            + Sum<<T as Mul>::Output>
+            + Copy
+            + Debug
+            + Sync
+            + Send
+            + PartialOrd
+            + 'static,
+    > MixtureOfFactorAnalyzers<T>
+where
+    for<'a> &'a T: Sub<&'a T, Output = T>,
+    T: FromStr,
+    Vec<T>: FromIterator<<T as Div>::Output>,
+{
+    /// Returns a new MixtureOfFactorAnalyzers struct with q latent factors
+    ///
+    /// The uniquenesses are estimated per component unless they are shared.
+    pub fn new(
+        k: usize,
+        factors: usize,
+        seed: u64,
+        mixtures: Vec<T>,
+        max_steps: i32,
+        tolerance: T,
+        shared_uniquenesses: bool,
+    ) -> MixtureOfFactorAnalyzers<T> {
+        Self {
+            means: Matrix::empty(),
+            loadings: Vec::new(),
+            uniquenesses: Vec::new(),
+            k,
+            factors,
+            seed,
+            mixtures,
+            tolerance,
+            final_difference: 1000.0,
+            max_steps,
+            steps: 0,
+            gammas: Matrix::empty(),
+            log_likelihood: T::neg_infinity(),
+            shared_uniquenesses,
+        }
+    }
+
+    /// Returns the fitted means, one row per component
+    pub fn means(&self) -> &Matrix<T> {
+        &self.means
+    }
+
+    /// Returns the number of free parameters of the fitted model
+    ///
+    /// The loadings are only defined up to a rotation, hence the q(q-1)/2 removed.
+    pub fn number_parameters(&self) -> usize {
+        let p = self.means.columns;
+        let q = self.factors.min(p);
+        let uniquenesses = if self.shared_uniquenesses {
+            p
+        } else {
+            self.k * p
+        };
+
+        self.k * p
+            + self.k.saturating_sub(1)
+            + self.k * (p * q - q * q.saturating_sub(1) / 2)
+            + uniquenesses
+    }
+
+    /// Computes I + Λ^T Ψ^-1 Λ for the given component
+    fn inner_matrix(&self, component: usize) -> Matrix<T> {
+        let loadings = &self.loadings[component];
+        let uniquenesses = &self.uniquenesses[component];
+        let q = loadings.columns;
+
+        let mut inner: Matrix<T> = Matrix::zeroes(q, q);
+        (0..q).for_each(|a| {
+            (0..q).for_each(|b| {
+                inner[a][b] = (0..loadings.rows).fold(T::zero(), |acc, j| {
+                    acc + loadings[j][a] * loadings[j][b] / uniquenesses[j]
+                });
+            });
+            inner[a][a] = inner[a][a] + T::one();
+        });
+
+        inner
+    }
+
+    /// Computes the log density of the data under one component
+    fn log_density(&self, data: &Matrix<T>, component: usize) -> Result<Vec<T>, String> {
+        let loadings = &self.loadings[component];
+        let uniquenesses = &self.uniquenesses[component];
+        let means = &self.means[component];
+
+        let inner_lower = cholesky_decomposition(&self.inner_matrix(component))?;
+
+        let two: T = T::from(2.0).unwrap();
+        let log_two_pi: T = (two * T::from(std::f64::consts::PI).unwrap()).ln();
+        let log_determinant = uniquenesses.iter().fold(T::zero(), |acc, u| acc + u.ln())
+            + cholesky_log_determinant(&inner_lower);
+        let constant = T::from(data.columns).unwrap() * log_two_pi + log_determinant;
+
+        Ok((0..data.rows)
+            .map(|row| {
+                let centered: Vec<T> = (0..data.columns).map(|j| data[row][j] - means[j]).collect();
+
+                let diagonal_part = (0..data.columns).fold(T::zero(), |acc, j| {
+                    acc + centered[j] * centered[j] / uniquenesses[j]
+                });
+                let projected: Vec<T> = (0..loadings.columns)
+                    .map(|a| {
+                        (0..data.columns).fold(T::zero(), |acc, j| {
+                            acc + loadings[j][a] * centered[j] / uniquenesses[j]
+                        })
+                    })
+                    .collect();
+                let solved = forward_substitution(&inner_lower, &projected).unwrap();
+                let low_rank_part = solved.iter().fold(T::zero(), |acc, s| acc + *s * *s);
+
+                -(constant + diagonal_part - low_rank_part) / two
+            })
+            .collect())
+    }
+
+    /// Computes the responsibilities of each component and the log likelihood
+    pub fn e_step(&self, data: &Matrix<T>) -> Result<(Matrix<T>, T), &'static str> {
+        let mut gammas: Matrix<T> = Matrix::zeroes(self.k, data.rows);
+
+        for i in 0..self.k {
+            let log_densities = self
+                .log_density(data, i)
+                .map_err(|_| "Factor analyzer covariance is not positive definite")?;
+            let log_mixture = self.mixtures[i].ln();
+
+            log_densities
+                .iter()
+                .enumerate()
+                .for_each(|(idx, density)| gammas[i][idx] = *density + log_mixture);
+        }
+
+        let log_likelihood = normalize_log_densities(&mut gammas);
+
+        Ok((gammas, log_likelihood))
+    }
+
+    /// Returns the probability of each component for each data point
+    pub fn predict_proba(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        if self.means.is_empty() {
+            return Err("The model has not been fitted");
+        }
+
+        let (gammas, _) = self.e_step(data)?;
+        Ok(gammas)
+    }
+
+    /// Fits the Mixture of Factor Analyzers to the data using EM
+    pub fn fit(
+        &mut self,
+        data: &Matrix<T>,
+        distance: &dyn distances::Distance<T>,
+    ) -> Result<&'static str, &'static str> {
+        let p = data.columns;
+        let q = self.factors.min(p);
+
+        if q == 0 {
+            return Err("At least one latent factor is needed");
+        }
+
+        let mut init = kmeans::Kmeans::init(self.k, data, self.seed, distance)?;
+        init.fit(data, None)?;
+
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(self.seed);
+        let floor: T = T::from(1e-6).unwrap();
+
+        let mut means: Matrix<T> = Matrix::empty();
+        self.loadings = Vec::with_capacity(self.k);
+        self.uniquenesses = Vec::with_capacity(self.k);
+
+        // Only the variances of the Kmeans clusters are needed, random loadings scaled
+        // by them break the rotation symmetry
+        let overall = means_zs(data, &Partition::single(data.rows), 0).unwrap();
+        let overall_variances: Vec<T> = (0..p)
+            .map(|j| {
+                (0..data.rows).fold(T::zero(), |acc, row| {
+                    acc + (data[row][j] - overall[j]) * (data[row][j] - overall[j])
+                }) / T::from(data.rows).unwrap()
+            })
+            .collect();
+
+        let partition = &init.partition;
+        for cluster in 0..self.k {
+            let m = means_zs(data, partition, cluster).unwrap();
+            let count = partition.sizes()[cluster];
+
+            let variances: Vec<T> = if count > 1 {
+                (0..p)
+                    .map(|j| {
+                        let variance = partition.members(cluster).fold(T::zero(), |acc, row| {
+                            acc + (data[row][j] - m[j]) * (data[row][j] - m[j])
+                        }) / T::from(count).unwrap();
+                        variance.max(floor)
+                    })
+                    .collect()
+            } else {
+                overall_variances.iter().map(|v| v.max(floor)).collect()
+            };
+
+            let mut loadings: Matrix<T> = Matrix::zeroes(p, q);
+            (0..p).for_each(|j| {
+                (0..q).for_each(|a| {
+                    let scale = (variances[j] / T::from(2 * q).unwrap()).sqrt();
+                    loadings[j][a] = scale * T::from(rng.gen_range(-1.0..1.0)).unwrap();
+                })
+            });
+
+            self.loadings.push(loadings);
+            self.uniquenesses.push(
+                variances
+                    .iter()
+                    .map(|v| *v / T::from(2.0).unwrap())
+                    .collect(),
+            );
+            let m: Vec<T> = match count {
+                0 => overall.clone(),
+                _ => m,
+            };
+            means.append_vector(&m, 0).expect("Unable to append row");
+        }
+
+        self.means = means;
+
+        let mut counter = 0;
+
+        // EM Loop
+        loop {
+            // E step
+            let (gammas, log_likelihood) = self.e_step(data)?;
+            self.log_likelihood = log_likelihood;
+
+            // M step
+            let nk = gammas.sum(1).unwrap();
+
+            let mut new_means: Matrix<T> = Matrix::zeroes(self.k, p);
+            let mut new_loadings: Vec<Matrix<T>> = Vec::with_capacity(self.k);
+            let mut new_uniquenesses: Vec<Vec<T>> = Vec::with_capacity(self.k);
+
+            for i in 0..self.k {
+                if nk[0][i] <= T::epsilon() {
+                    return Err("Degenerate component");
+                }
+
+                (0..data.rows).for_each(|idx| {
+                    (0..p).for_each(|j| {
+                        new_means[i][j] = new_means[i][j] + gammas[i][idx] * data[idx][j];
+                    })
+                });
+                new_means[i]
+                    .iter_mut()
+                    .for_each(|mean| *mean = *mean / nk[0][i]);
+
+                // β = (I + Λ^T Ψ^-1 Λ)^-1 Λ^T Ψ^-1, a (q x p) matrix
+                let inner_inverse = cholesky_inverse(&self.inner_matrix(i))
+                    .map_err(|_| "Factor analyzer covariance is not positive definite")?;
+                let loadings = &self.loadings[i];
+                let uniquenesses = &self.uniquenesses[i];
+
+                let mut beta: Matrix<T> = Matrix::zeroes(q, p);
+                (0..q).for_each(|a| {
+                    (0..p).for_each(|j| {
+                        beta[a][j] = (0..q).fold(T::zero(), |acc, b| {
+                            acc + inner_inverse[a][b] * loadings[j][b]
+                        }) / uniquenesses[j];
+                    })
+                });
+
+                // S β^T and the diagonal of S, without building the (p x p) S
+                let mut s_beta: Matrix<T> = Matrix::zeroes(p, q);
+                let mut s_diagonal: Vec<T> = vec![T::zero(); p];
+
+                (0..data.rows).for_each(|idx| {
+                    let centered: Vec<T> = (0..p).map(|j| data[idx][j] - new_means[i][j]).collect();
+                    let projected: Vec<T> = (0..q)
+                        .map(|a| (0..p).fold(T::zero(), |acc, j| acc + beta[a][j] * centered[j]))
+                        .collect();
+
+                    (0..p).for_each(|j| {
+                        let weighted = gammas[i][idx] * centered[j];
+                        s_diagonal[j] = s_diagonal[j] + weighted * centered[j];
+                        (0..q).for_each(|a| s_beta[j][a] = s_beta[j][a] + weighted * projected[a]);
+                    });
+                });
+
+                s_beta.divide_by_scalar(nk[0][i]);
+                s_diagonal.iter_mut().for_each(|s| *s = *s / nk[0][i]);
+
+                // E[z z^T] = I - β Λ + β S β^T
+                let mut second_moment: Matrix<T> = Matrix::zeroes(q, q);
+                (0..q).for_each(|a| {
+                    (0..q).for_each(|b| {
+                        second_moment[a][b] = (0..p).fold(T::zero(), |acc, j| {
+                            acc - beta[a][j] * loadings[j][b] + beta[a][j] * s_beta[j][b]
+                        });
+                    });
+                    second_moment[a][a] = second_moment[a][a] + T::one();
+                });
+
+                let second_moment_inverse =
+                    cholesky_inverse(&second_moment).map_err(|_| "Degenerate component")?;
+
+                let mut updated_loadings: Matrix<T> = Matrix::zeroes(p, q);
+                (0..p).for_each(|j| {
+                    (0..q).for_each(|a| {
+                        updated_loadings[j][a] = (0..q).fold(T::zero(), |acc, b| {
+                            acc + s_beta[j][b] * second_moment_inverse[b][a]
+                        });
+                    })
+                });
+
+                let updated_uniquenesses: Vec<T> = (0..p)
+                    .map(|j| {
+                        let explained = (0..q).fold(T::zero(), |acc, a| {
+                            acc + updated_loadings[j][a] * s_beta[j][a]
+                        });
+                        (s_diagonal[j] - explained).max(floor)
+                    })
+                    .collect();
+
+                new_loadings.push(updated_loadings);
+                new_uniquenesses.push(updated_uniquenesses);
+            }
+
+            if self.shared_uniquenesses {
+                let pooled: Vec<T> = (0..p)
+                    .map(|j| {
+                        (0..self.k)
+                            .fold(T::zero(), |acc, i| acc + nk[0][i] * new_uniquenesses[i][j])
+                            / T::from(data.rows).unwrap()
+                    })
+                    .collect();
+                new_uniquenesses = vec![pooled; self.k];
+            }
+
+            counter += 1;
+
+            let mean_error = mean_squared_error(&self.means.content, &new_means.content).unwrap();
+
+            if counter == self.max_steps {
+                return Err("Did not converge");
+            }
+
+            if mean_error < self.tolerance {
+                self.gammas = gammas;
+                self.final_difference = T::to_f32(&mean_error).unwrap();
+                return Ok("Converged");
+            }
+
+            self.means = new_means;
+            self.loadings = new_loadings;
+            self.uniquenesses = new_uniquenesses;
+            self.mixtures = nk
+                .content
+                .iter()
+                .map(|n| *n / T::from(data.rows).unwrap())
+                .collect();
+            self.steps += 1;
+        }
+    }
+}
+
+impl<
+        T: Float
+            + Div
+            + Mul
+            + Add
+            + Sub<Output = T>
+            + Sum<<T as Mul>::Output>
+            + Copy
+            + Debug
+            + Sync
+            + Send
+            + PartialOrd
+            + 'static,
+    > MixtureModel<T> for MixtureOfFactorAnalyzers<T>
+where
+    for<'a> &'a T: Sub<&'a T, Output = T>,
+    T: FromStr,
+    Vec<T>: FromIterator<<T as Div>::Output>,
+{
+    fn fit(
+        &mut self,
+        data: &Matrix<T>,
+        distance: &dyn distances::Distance<T>,
+        labels: Option<&[Option<usize>]>,
+        weights: Option<&[T]>,
+    ) -> Result<&'static str, &'static str> {
+        if labels.is_some() {
+            return Err("Known labels are only supported by the GaussianMixtureModel");
+        }
+
+        if weights.is_some() {
+            return Err("Row weights are only supported by the GaussianMixtureModel");
+        }
+
+        MixtureOfFactorAnalyzers::fit(self, data, distance)
+    }
+
+    fn predict_proba(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        MixtureOfFactorAnalyzers::predict_proba(self, data)
+    }
+
+    fn responsibilities(&self) -> &Matrix<T> {
+        &self.gammas
+    }
+
+    fn log_likelihood(&self) -> T {
+        self.log_likelihood
+    }
+
+    fn number_parameters(&self) -> usize {
+        MixtureOfFactorAnalyzers::number_parameters(self)
+    }
+
+    fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    fn final_difference(&self) -> f32 {
+        self.final_difference
+    }
+}
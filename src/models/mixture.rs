@@ -0,0 +1,86 @@
+//! Interface shared by the mixture models used for clustering
+
+use num::Float;
+
+use super::distances::Distance;
+
+use crate::calculations::matrix::Matrix;
+
+/// Common interface of the mixture models
+///
+/// It is what CLUSTVARSEL relies on to compare the variable subsets, so any model
+/// implementing it can be used for the selection.
+pub trait MixtureModel<T: Float + 'static> {
+    /// Fits the model to the data
+    ///
+    /// Rows with a known label keep it during the fit and weighted rows count
+    /// as many times as their weight. Models that do not support partially
+    /// known labels or weights return an error when some are given.
+    fn fit(
+        &mut self,
+        data: &Matrix<T>,
+        distance: &dyn Distance<T>,
+        labels: Option<&[Option<usize>]>,
+        weights: Option<&[T]>,
+    ) -> Result<&'static str, &'static str>;
+
+    /// Returns the probability of each component (rows) for each data point (columns)
+    fn predict_proba(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str>;
+
+    /// Returns the most likely component of each data point
+    fn predict(&self, data: &Matrix<T>) -> Result<Vec<usize>, &'static str> {
+        let probabilities = self.predict_proba(data)?;
+
+        Ok((0..probabilities.columns)
+            .map(|col| {
+                (0..probabilities.rows).fold(0, |best, row| {
+                    if probabilities[row][col] > probabilities[best][col] {
+                        row
+                    } else {
+                        best
+                    }
+                })
+            })
+            .collect())
+    }
+
+    /// Returns the responsibilities computed during the fit
+    fn responsibilities(&self) -> &Matrix<T>;
+
+    /// Returns the log likelihood of the fitted model
+    fn log_likelihood(&self) -> T;
+
+    /// Returns the number of free parameters of the fitted model
+    fn number_parameters(&self) -> usize;
+
+    /// Returns the number of EM steps performed during the fit
+    fn steps(&self) -> u64;
+
+    /// Returns the difference between the last two steps
+    fn final_difference(&self) -> f32;
+}
+
+/// Fixes the responsibilities of the rows whose component is known
+///
+/// Returns what has to be added to the weighted log likelihood computed over
+/// all the components so that the labelled rows only count their own component.
+pub fn fix_known_labels<T: Float + 'static>(
+    gammas: &mut Matrix<T>,
+    labels: &[Option<usize>],
+    weights: &[T],
+) -> T {
+    let mut correction: T = T::zero();
+
+    labels.iter().enumerate().for_each(|(idx, label)| {
+        if let Some(component) = label {
+            if weights[idx] != T::zero() {
+                correction = correction + weights[idx] * gammas[*component][idx].ln();
+            }
+
+            (0..gammas.rows)
+                .for_each(|i| gammas[i][idx] = if i == *component { T::one() } else { T::zero() });
+        }
+    });
+
+    correction
+}
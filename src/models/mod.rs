@@ -1,4 +1,15 @@
+pub mod bootstrap;
 pub mod clustvarsel;
+pub mod discriminant;
 pub mod distances;
 pub mod gmm;
 pub mod kmeans;
+pub mod kmedoids;
+pub mod merging;
+pub mod mfa;
+pub mod mixture;
+pub mod model_selection;
+#[cfg(feature = "serde")]
+pub mod persistence;
+pub mod tmm;
+pub mod vbgmm;
@@ -0,0 +1,157 @@
+//! mclust-style model selection over the number of clusters and covariance models
+
+use std::fmt::Debug;
+use std::iter::Sum;
+use std::ops::{Add, Div, Mul, Sub};
+use std::str::FromStr;
+
+use num::Float;
+use rayon::prelude::*;
+
+use super::distances::Distance;
+use super::gmm::{CovarianceModel, GaussianMixtureModel};
+
+use crate::calculations::matrix::Matrix;
+
+/// BIC values of every (number of clusters, covariance model) combination
+///
+/// `values[g][m]` holds the BIC of `clusters[g]` components with `models[m]`,
+/// or None if the fit failed.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BicTable<T> {
+    pub clusters: Vec<usize>,
+    pub models: Vec<CovarianceModel>,
+    pub values: Vec<Vec<Option<T>>>,
+}
+
+impl<T: Float> BicTable<T> {
+    /// Returns the n best (number of clusters, covariance model, BIC), best first
+    pub fn top(&self, n: usize) -> Vec<(usize, CovarianceModel, T)> {
+        let mut ranked: Vec<(usize, CovarianceModel, T)> = self
+            .values
+            .iter()
+            .enumerate()
+            .flat_map(|(g, row)| {
+                row.iter().enumerate().filter_map(move |(m, bic)| {
+                    bic.filter(|bic| bic.is_finite())
+                        .map(|bic| (self.clusters[g], self.models[m], bic))
+                })
+            })
+            .collect();
+
+        ranked.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+        ranked.truncate(n);
+        ranked
+    }
+}
+
+/// Result of the model selection
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize + Float",
+        deserialize = "T: serde::Deserialize<'de> + Float"
+    ))
+)]
+pub struct ModelSelection<T> {
+    pub table: BicTable<T>,
+    pub top_models: Vec<(usize, CovarianceModel, T)>,
+    pub best_model: GaussianMixtureModel<T>,
+}
+
+/// Fits a GaussianMixtureModel for every number of clusters and covariance model
+///
+/// Mirrors `mclustBIC`: the BIC follows the 2 * loglik - npar * ln(n) convention
+/// so the best model has the largest value. The fits run in parallel.
+#[allow(clippy::too_many_arguments)]
+pub fn mclust_bic<T>(
+    data: &Matrix<T>,
+    clusters: &[usize],
+    models: &[CovarianceModel],
+    seed: u64,
+    max_steps: i32,
+    tolerance: T,
+    distance: &dyn Distance<T>,
+    top: usize,
+) -> Result<ModelSelection<T>, &'static str>
+where
+    T: Float
+        + Div
+        + Mul
+        + Add
+        + Sub<Output = T>
+        + Sum<<T as Mul>::Output>
+        + Copy
+        + Debug
+        + Sync
+        + Send
+        + PartialOrd
+        + FromStr
+        + 'static,
+    for<'a> &'a T: Sub<&'a T, Output = T>,
+    Vec<T>: FromIterator<<T as Div>::Output>,
+{
+    if clusters.is_empty() || models.is_empty() {
+        return Err("At least one number of clusters and one covariance model are needed");
+    }
+
+    let combinations: Vec<(usize, usize)> = (0..clusters.len())
+        .flat_map(|g| (0..models.len()).map(move |m| (g, m)))
+        .collect();
+
+    let fitted: Vec<(usize, usize, Option<GaussianMixtureModel<T>>)> = combinations
+        .into_par_iter()
+        .map(|(g, m)| {
+            let k = clusters[g];
+            let mut gmm = GaussianMixtureModel::new(
+                k,
+                seed,
+                vec![T::one() / T::from(k).unwrap(); k],
+                max_steps,
+                tolerance,
+            );
+            gmm.covariance_model = models[m];
+
+            match gmm.fit(data, distance, None, None) {
+                Ok(_) => (g, m, Some(gmm)),
+                Err(_) => (g, m, None),
+            }
+        })
+        .collect();
+
+    let mut values: Vec<Vec<Option<T>>> = vec![vec![None; models.len()]; clusters.len()];
+    let mut best_model: Option<GaussianMixtureModel<T>> = None;
+
+    for (g, m, gmm) in fitted {
+        if let Some(gmm) = gmm {
+            let bic = gmm.bic(data.rows);
+            values[g][m] = Some(bic);
+
+            let is_better = match &best_model {
+                Some(best) => bic > best.bic(data.rows),
+                None => bic.is_finite(),
+            };
+
+            if is_better {
+                best_model = Some(gmm);
+            }
+        }
+    }
+
+    let table = BicTable {
+        clusters: clusters.to_vec(),
+        models: models.to_vec(),
+        values,
+    };
+
+    match best_model {
+        Some(best_model) => Ok(ModelSelection {
+            top_models: table.top(top),
+            table,
+            best_model,
+        }),
+        None => Err("None of the models could be fitted"),
+    }
+}
@@ -0,0 +1,144 @@
+//! Saving and loading of fitted models and selection results
+//!
+//! Models are wrapped in a versioned envelope and written either as JSON or
+//! as a compact binary (bincode). Loading a file written with another format
+//! version fails instead of returning a partially filled model.
+use std::{error::Error, fs, path::Path};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Version of the on-disk format, bumped whenever a saved struct changes
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Envelope written to disk around every model
+#[derive(Serialize, Deserialize)]
+struct Saved<M> {
+    format_version: u32,
+    model: M,
+}
+
+/// Returns an error if the file was written with another format version
+fn check_version(format_version: u32) -> Result<(), Box<dyn Error>> {
+    if format_version != FORMAT_VERSION {
+        return Err(format!(
+            "Unsupported format version {format_version}, expected {FORMAT_VERSION}"
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Serializes a model to a JSON string
+pub fn to_json<M: Serialize>(model: &M) -> Result<String, Box<dyn Error>> {
+    let saved = Saved {
+        format_version: FORMAT_VERSION,
+        model,
+    };
+
+    Ok(serde_json::to_string(&saved)?)
+}
+
+/// Deserializes a model from a JSON string
+pub fn from_json<M: DeserializeOwned>(json: &str) -> Result<M, Box<dyn Error>> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+
+    let format_version = value
+        .get("format_version")
+        .and_then(|version| version.as_u64())
+        .ok_or("Missing format version")?;
+    check_version(format_version as u32)?;
+
+    let saved: Saved<M> = serde_json::from_value(value)?;
+    Ok(saved.model)
+}
+
+/// Serializes a model to the compact binary format
+pub fn to_binary<M: Serialize>(model: &M) -> Result<Vec<u8>, Box<dyn Error>> {
+    let saved = Saved {
+        format_version: FORMAT_VERSION,
+        model,
+    };
+
+    Ok(bincode::serialize(&saved)?)
+}
+
+/// Deserializes a model from the compact binary format
+pub fn from_binary<M: DeserializeOwned>(bytes: &[u8]) -> Result<M, Box<dyn Error>> {
+    // The version is the first field, it is read alone so that
+    // an older layout is reported as such and not as corrupted data
+    let format_version: u32 = bincode::deserialize(bytes)?;
+    check_version(format_version)?;
+
+    let saved: Saved<M> = bincode::deserialize(bytes)?;
+    Ok(saved.model)
+}
+
+/// Writes a model to a JSON file
+pub fn save_json<M: Serialize, P: AsRef<Path>>(model: &M, path: P) -> Result<(), Box<dyn Error>> {
+    fs::write(path, to_json(model)?)?;
+    Ok(())
+}
+
+/// Reads a model from a JSON file
+pub fn load_json<M: DeserializeOwned, P: AsRef<Path>>(path: P) -> Result<M, Box<dyn Error>> {
+    from_json(&fs::read_to_string(path)?)
+}
+
+/// Writes a model to a binary file
+pub fn save_binary<M: Serialize, P: AsRef<Path>>(model: &M, path: P) -> Result<(), Box<dyn Error>> {
+    fs::write(path, to_binary(model)?)?;
+    Ok(())
+}
+
+/// Reads a model from a binary file
+pub fn load_binary<M: DeserializeOwned, P: AsRef<Path>>(path: P) -> Result<M, Box<dyn Error>> {
+    from_binary(&fs::read(path)?)
+}
+
+/// Floats that can be infinite, such as the log likelihood of an unfitted model
+///
+/// JSON has no infinity, so these fields are written as an enum instead of a number.
+pub mod non_finite {
+    use num::Float;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    enum Value<T> {
+        Finite(T),
+        Infinity,
+        NegativeInfinity,
+        NaN,
+    }
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Float + Serialize,
+        S: Serializer,
+    {
+        let value = if value.is_nan() {
+            Value::NaN
+        } else if value.is_finite() {
+            Value::Finite(*value)
+        } else if value.is_sign_positive() {
+            Value::Infinity
+        } else {
+            Value::NegativeInfinity
+        };
+
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: Float + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(match Value::deserialize(deserializer)? {
+            Value::Finite(value) => value,
+            Value::Infinity => T::infinity(),
+            Value::NegativeInfinity => T::neg_infinity(),
+            Value::NaN => T::nan(),
+        })
+    }
+}
@@ -0,0 +1,369 @@
+use std::fmt::Debug;
+use std::iter::Sum;
+use std::ops::{Add, Div, Mul, Sub};
+use std::str::FromStr;
+
+use num::Float;
+
+use super::mixture::MixtureModel;
+use super::{distances, kmeans};
+
+use crate::calculations::linear_algebra::cholesky_decomposition;
+use crate::calculations::matrix::Matrix;
+use crate::calculations::partition::Partition;
+use crate::calculations::stats::{
+    covariance, digamma, log_multivariate_t, mahalanobis_distances, means_zs,
+    normalize_log_densities,
+};
+use crate::helpers::mean_squared_error;
+
+/// Struct containing all the information about the Student t Mixture Model
+///
+/// The heavier tails of the t distribution make the fit robust to outliers.
+/// Each data point gets a weight, smaller the further it is from a component.
+pub struct StudentTMixtureModel<T> {
+    means: Matrix<T>,
+    covariance_matrices: Vec<Matrix<T>>,
+    k: usize,
+    seed: u64,
+    mixtures: Vec<T>,
+    tolerance: T,
+    pub final_difference: f32,
+    max_steps: i32,
+    pub steps: u64,
+    pub gammas: Matrix<T>,
+    pub log_likelihood: T,
+    pub degrees_of_freedom: Vec<T>,
+    pub shared_degrees_of_freedom: bool,
+}
+
+impl<
+        T: Float
+            + Div
+            + Mul
+            + Add
+            + Sub<Output = T>
+            + Sum<<T as Mul>::Output>
+            + Copy
+            + Debug
+            + Sync
+            + Send
+            + PartialOrd
+            + 'static,
+    > StudentTMixtureModel<T>
+where
+    for<'a> &'a T: Sub<&'a T, Output = T>,
+    T: FromStr,
+    Vec<T>: FromIterator<<T as Div>::Output>,
+{
+    /// Returns a new StudentTMixtureModel struct
+    ///
+    /// The degrees of freedom are estimated per component unless they are shared.
+    pub fn new(
+        k: usize,
+        seed: u64,
+        mixtures: Vec<T>,
+        max_steps: i32,
+        tolerance: T,
+        shared_degrees_of_freedom: bool,
+    ) -> StudentTMixtureModel<T> {
+        Self {
+            means: Matrix::empty(),
+            covariance_matrices: Vec::new(),
+            k,
+            seed,
+            mixtures,
+            tolerance,
+            final_difference: 1000.0,
+            max_steps,
+            steps: 0,
+            gammas: Matrix::empty(),
+            log_likelihood: T::neg_infinity(),
+            degrees_of_freedom: vec![T::from(30.0).unwrap(); k],
+            shared_degrees_of_freedom,
+        }
+    }
+
+    /// Returns the fitted means, one row per component
+    pub fn means(&self) -> &Matrix<T> {
+        &self.means
+    }
+
+    /// Returns the number of free parameters of the fitted model
+    pub fn number_parameters(&self) -> usize {
+        let p = self.means.columns;
+        let dof = if self.shared_degrees_of_freedom {
+            1
+        } else {
+            self.k
+        };
+
+        self.k * p + self.k.saturating_sub(1) + self.k * p * (p + 1) / 2 + dof
+    }
+
+    /// Computes the responsibilities of each component and the log likelihood
+    pub fn e_step(&self, data: &Matrix<T>) -> Result<(Matrix<T>, T), &'static str> {
+        let mut gammas: Matrix<T> = Matrix::zeroes(self.k, data.rows);
+
+        for i in 0..self.k {
+            let log_densities = log_multivariate_t(
+                data,
+                &self.covariance_matrices[i],
+                &self.means[i],
+                self.degrees_of_freedom[i],
+            )
+            .map_err(|_| "Covariance matrix is not positive definite")?;
+            let log_mixture = self.mixtures[i].ln();
+
+            log_densities
+                .iter()
+                .enumerate()
+                .for_each(|(idx, density)| gammas[i][idx] = *density + log_mixture);
+        }
+
+        let log_likelihood = normalize_log_densities(&mut gammas);
+
+        Ok((gammas, log_likelihood))
+    }
+
+    /// Returns the probability of each component for each data point
+    pub fn predict_proba(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        if self.means.is_empty() {
+            return Err("The model has not been fitted");
+        }
+
+        let (gammas, _) = self.e_step(data)?;
+        Ok(gammas)
+    }
+
+    /// Solves the degrees of freedom equation by bisection
+    ///
+    /// `constant` holds the part of the equation that does not depend on the new value.
+    fn solve_degrees_of_freedom(constant: T) -> T {
+        let two: T = T::from(2.0).unwrap();
+        let equation = |dof: T| -digamma(dof / two) + (dof / two).ln() + T::one() + constant;
+
+        let mut low: T = T::from(1e-3).unwrap();
+        let mut high: T = T::from(200.0).unwrap();
+
+        // The equation is decreasing, past the bounds the closest one is kept
+        if equation(high) > T::zero() {
+            return high;
+        }
+        if equation(low) < T::zero() {
+            return low;
+        }
+
+        for _ in 0..100 {
+            let middle = (low + high) / two;
+
+            if equation(middle) > T::zero() {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+
+        (low + high) / two
+    }
+
+    /// Fits the Student t Mixture Model to the data using EM
+    pub fn fit(
+        &mut self,
+        data: &Matrix<T>,
+        distance: &dyn distances::Distance<T>,
+    ) -> Result<&'static str, &'static str> {
+        let mut init = kmeans::Kmeans::init(self.k, data, self.seed, distance)?;
+        init.fit(data, None)?;
+
+        let mut means: Matrix<T> = Matrix::empty();
+        self.covariance_matrices = Vec::with_capacity(self.k);
+
+        // Clusters too small to estimate a covariance start from the pooled one
+        let pooled: Matrix<T> = covariance(data, &Partition::single(data.rows), 0);
+
+        for cluster in 0..self.k {
+            let cov: Matrix<T> = covariance(data, &init.partition, cluster);
+            match cholesky_decomposition(&cov) {
+                Ok(_) => self.covariance_matrices.push(cov),
+                Err(_) => self.covariance_matrices.push(pooled.clone()),
+            }
+            let m = means_zs(data, &init.partition, cluster).unwrap();
+            means.append_vector(&m, 0).expect("Unable to append row");
+        }
+
+        self.means = means;
+        self.degrees_of_freedom = vec![T::from(30.0).unwrap(); self.k];
+
+        let two: T = T::from(2.0).unwrap();
+        let p: T = T::from(data.columns).unwrap();
+        let mut counter = 0;
+
+        // EM Loop
+        loop {
+            // E step
+            let (gammas, log_likelihood) = self.e_step(data)?;
+            self.log_likelihood = log_likelihood;
+
+            // Weight of each data point, smaller for the outliers
+            let mut weights: Matrix<T> = Matrix::zeroes(self.k, data.rows);
+
+            for i in 0..self.k {
+                let (distances, _) =
+                    mahalanobis_distances(data, &self.covariance_matrices[i], &self.means[i])
+                        .map_err(|_| "Covariance matrix is not positive definite")?;
+
+                distances.iter().enumerate().for_each(|(idx, distance)| {
+                    weights[i][idx] =
+                        (self.degrees_of_freedom[i] + p) / (self.degrees_of_freedom[i] + *distance)
+                });
+            }
+
+            // M step
+            let nk = gammas.sum(1).unwrap();
+
+            let mut new_means: Matrix<T> = Matrix::zeroes(self.k, data.columns);
+            let mut covs: Vec<Matrix<T>> = Vec::with_capacity(self.k);
+
+            for i in 0..self.k {
+                if nk[0][i] <= T::epsilon() {
+                    return Err("Degenerate component");
+                }
+
+                let scaled: Vec<T> = (0..data.rows)
+                    .map(|idx| gammas[i][idx] * weights[i][idx])
+                    .collect();
+                let total_scaled = scaled.iter().fold(T::zero(), |acc, s| acc + *s);
+
+                (0..data.rows).for_each(|idx| {
+                    (0..data.columns).for_each(|col| {
+                        new_means[i][col] = new_means[i][col] + scaled[idx] * data[idx][col];
+                    })
+                });
+                new_means[i]
+                    .iter_mut()
+                    .for_each(|mean| *mean = *mean / total_scaled);
+
+                let mut cov: Matrix<T> = Matrix::zeroes(data.columns, data.columns);
+                (0..data.rows).for_each(|idx| {
+                    (0..data.columns).for_each(|col1| {
+                        (0..data.columns).for_each(|col2| {
+                            cov[col1][col2] = cov[col1][col2]
+                                + scaled[idx]
+                                    * (data[idx][col1] - new_means[i][col1])
+                                    * (data[idx][col2] - new_means[i][col2]);
+                        })
+                    })
+                });
+                cov.divide_by_scalar(nk[0][i]);
+                covs.push(cov);
+            }
+
+            // Degrees of freedom
+            let dof_terms: Vec<T> = (0..self.k)
+                .map(|i| {
+                    let half = (self.degrees_of_freedom[i] + p) / two;
+                    (0..data.rows).fold(T::zero(), |acc, idx| {
+                        acc + gammas[i][idx] * (weights[i][idx].ln() - weights[i][idx])
+                    }) + nk[0][i] * (digamma(half) - half.ln())
+                })
+                .collect();
+
+            let degrees_of_freedom: Vec<T> = if self.shared_degrees_of_freedom {
+                let total = dof_terms.iter().fold(T::zero(), |acc, t| acc + *t);
+                let dof = Self::solve_degrees_of_freedom(total / T::from(data.rows).unwrap());
+                vec![dof; self.k]
+            } else {
+                (0..self.k)
+                    .map(|i| Self::solve_degrees_of_freedom(dof_terms[i] / nk[0][i]))
+                    .collect()
+            };
+
+            counter += 1;
+
+            let mean_error = mean_squared_error(&self.means.content, &new_means.content).unwrap();
+
+            if counter == self.max_steps {
+                return Err("Did not converge");
+            }
+
+            if mean_error < self.tolerance {
+                self.gammas = gammas;
+                self.final_difference = T::to_f32(&mean_error).unwrap();
+                return Ok("Converged");
+            }
+
+            self.means = new_means;
+            self.covariance_matrices = covs;
+            self.degrees_of_freedom = degrees_of_freedom;
+            self.mixtures = nk
+                .content
+                .iter()
+                .map(|n| *n / T::from(data.rows).unwrap())
+                .collect();
+            self.steps += 1;
+        }
+    }
+}
+
+impl<
+        T: Float
+            + Div
+            + Mul
+            + Add
+            + Sub<Output = T>
+            + Sum<<T as Mul>::Output>
+            + Copy
+            + Debug
+            + Sync
+            + Send
+            + PartialOrd
+            + 'static,
+    > MixtureModel<T> for StudentTMixtureModel<T>
+where
+    for<'a> &'a T: Sub<&'a T, Output = T>,
+    T: FromStr,
+    Vec<T>: FromIterator<<T as Div>::Output>,
+{
+    fn fit(
+        &mut self,
+        data: &Matrix<T>,
+        distance: &dyn distances::Distance<T>,
+        labels: Option<&[Option<usize>]>,
+        weights: Option<&[T]>,
+    ) -> Result<&'static str, &'static str> {
+        if labels.is_some() {
+            return Err("Known labels are only supported by the GaussianMixtureModel");
+        }
+
+        if weights.is_some() {
+            return Err("Row weights are only supported by the GaussianMixtureModel");
+        }
+
+        StudentTMixtureModel::fit(self, data, distance)
+    }
+
+    fn predict_proba(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        StudentTMixtureModel::predict_proba(self, data)
+    }
+
+    fn responsibilities(&self) -> &Matrix<T> {
+        &self.gammas
+    }
+
+    fn log_likelihood(&self) -> T {
+        self.log_likelihood
+    }
+
+    fn number_parameters(&self) -> usize {
+        StudentTMixtureModel::number_parameters(self)
+    }
+
+    fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    fn final_difference(&self) -> f32 {
+        self.final_difference
+    }
+}
@@ -0,0 +1,531 @@
+//! Variational Bayesian Gaussian Mixture Model
+//!
+//! The mixture weights get a Dirichlet prior and every component a Gaussian-Wishart
+//! prior. With a small Dirichlet concentration the weight of the components the data
+//! does not need goes to zero, which selects the number of components.
+
+use std::fmt::Debug;
+use std::iter::Sum;
+use std::ops::{Add, Div, Mul, Sub};
+use std::str::FromStr;
+
+use num::Float;
+
+use super::{distances, kmeans};
+
+use crate::calculations::linear_algebra::{
+    cholesky_decomposition, cholesky_inverse, cholesky_log_determinant,
+};
+use crate::calculations::matrix::Matrix;
+use crate::calculations::partition::Partition;
+use crate::calculations::stats::{
+    covariance, digamma, ln_gamma, mahalanobis_distances, normalize_log_densities,
+};
+
+/// Responsibility weighted statistics of the data, used by the updates and the ELBO
+struct ComponentStatistics<T> {
+    nk: Vec<T>,
+    means: Matrix<T>,
+    scatters: Vec<Matrix<T>>,
+}
+
+/// Struct containing all the information about the variational Gaussian mixture
+pub struct VariationalGaussianMixture<T> {
+    k: usize,
+    seed: u64,
+    max_steps: i32,
+    tolerance: T,
+    /// Dirichlet concentration of the weights, small values prune the components
+    pub concentration_prior: T,
+    /// Scaling of the precision of the component means
+    pub mean_precision_prior: T,
+    /// Wishart degrees of freedom, defaults to the number of columns
+    pub degrees_of_freedom_prior: Option<T>,
+    /// Inverse of the Wishart scale matrix, defaults to the covariance of the data
+    pub covariance_prior: Option<Matrix<T>>,
+    prior_mean: Vec<T>,
+    prior_scale_inverse: Matrix<T>,
+    prior_degrees_of_freedom: T,
+    /// Posterior Dirichlet concentrations
+    pub concentrations: Vec<T>,
+    /// Posterior scaling of the precision of the means
+    pub mean_precisions: Vec<T>,
+    /// Posterior means, one row per component
+    pub means: Matrix<T>,
+    /// Inverses of the posterior Wishart scale matrices
+    pub scale_inverses: Vec<Matrix<T>>,
+    /// Posterior Wishart degrees of freedom
+    pub degrees_of_freedom: Vec<T>,
+    pub gammas: Matrix<T>,
+    /// Evidence lower bound after every step
+    pub elbo_trace: Vec<T>,
+    pub steps: u64,
+    pub final_difference: f32,
+}
+
+impl<
+        T: Float
+            + Div
+            + Mul
+            + Add
+            + Sub<Output = T>
+            + Sum<<T as Mul>::Output>
+            + Copy
+            + Debug
+            + Sync
+            + Send
+            + PartialOrd
+            + 'static,
+    > VariationalGaussianMixture<T>
+where
+    for<'a> &'a T: Sub<&'a T, Output = T>,
+    T: FromStr,
+    Vec<T>: FromIterator<<T as Div>::Output>,
+{
+    /// Returns a new VariationalGaussianMixture struct
+    ///
+    /// k is the maximum number of components, the fit decides how many are used.
+    pub fn new(
+        k: usize,
+        seed: u64,
+        max_steps: i32,
+        tolerance: T,
+        concentration_prior: T,
+    ) -> VariationalGaussianMixture<T> {
+        Self {
+            k,
+            seed,
+            max_steps,
+            tolerance,
+            concentration_prior,
+            mean_precision_prior: T::one(),
+            degrees_of_freedom_prior: None,
+            covariance_prior: None,
+            prior_mean: Vec::new(),
+            prior_scale_inverse: Matrix::empty(),
+            prior_degrees_of_freedom: T::zero(),
+            concentrations: Vec::new(),
+            mean_precisions: Vec::new(),
+            means: Matrix::empty(),
+            scale_inverses: Vec::new(),
+            degrees_of_freedom: Vec::new(),
+            gammas: Matrix::empty(),
+            elbo_trace: Vec::new(),
+            steps: 0,
+            final_difference: 1000.0,
+        }
+    }
+
+    /// Returns the expected weight of each component
+    pub fn effective_weights(&self) -> Vec<T> {
+        let total = self
+            .concentrations
+            .iter()
+            .fold(T::zero(), |acc, a| acc + *a);
+
+        self.concentrations.iter().map(|a| *a / total).collect()
+    }
+
+    /// Returns the number of components whose expected weight is above the threshold
+    pub fn number_effective_components(&self, threshold: T) -> usize {
+        self.effective_weights()
+            .iter()
+            .filter(|weight| **weight > threshold)
+            .count()
+    }
+
+    /// Expectation of the log of the weights under their Dirichlet posterior
+    fn expected_log_weights(&self) -> Vec<T> {
+        let total = self
+            .concentrations
+            .iter()
+            .fold(T::zero(), |acc, a| acc + *a);
+
+        self.concentrations
+            .iter()
+            .map(|a| digamma(*a) - digamma(total))
+            .collect()
+    }
+
+    /// Sum of digamma((dof + 1 - i) / 2) for i in 1..=p, shared by the Wishart expectations
+    fn multivariate_digamma(dof: T, p: usize) -> T {
+        let two: T = T::from(2.0).unwrap();
+
+        (1..=p).fold(T::zero(), |acc, i| {
+            acc + digamma((dof + T::one() - T::from(i).unwrap()) / two)
+        })
+    }
+
+    /// Log normalisation constant of a Wishart distribution given ln|W|
+    fn log_wishart_normalisation(log_determinant: T, dof: T, p: usize) -> T {
+        let two: T = T::from(2.0).unwrap();
+        let dimension: T = T::from(p).unwrap();
+        let pi: T = T::from(std::f64::consts::PI).unwrap();
+
+        let log_gammas = (1..=p).fold(T::zero(), |acc, i| {
+            acc + ln_gamma((dof + T::one() - T::from(i).unwrap()) / two)
+        });
+
+        -dof / two * log_determinant
+            - (dof * dimension / two * two.ln()
+                + dimension * (dimension - T::one()) / T::from(4.0).unwrap() * pi.ln()
+                + log_gammas)
+    }
+
+    /// Log normalisation constant of a Dirichlet distribution
+    fn log_dirichlet_normalisation(concentrations: &[T]) -> T {
+        let total = concentrations.iter().fold(T::zero(), |acc, a| acc + *a);
+
+        ln_gamma(total)
+            - concentrations
+                .iter()
+                .fold(T::zero(), |acc, a| acc + ln_gamma(*a))
+    }
+
+    /// Computes the unnormalised log responsibilities of every component (rows) for every data point
+    fn log_responsibilities(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        let two: T = T::from(2.0).unwrap();
+        let p = data.columns;
+        let dimension: T = T::from(p).unwrap();
+        let ln_two_pi: T = (two * T::from(std::f64::consts::PI).unwrap()).ln();
+
+        let log_weights = self.expected_log_weights();
+        let mut log_rho: Matrix<T> = Matrix::zeroes(self.k, data.rows);
+
+        for i in 0..self.k {
+            // The quadratic form uses W, the inverse of the stored matrix
+            let (distances, log_determinant) =
+                mahalanobis_distances(data, &self.scale_inverses[i], &self.means[i])
+                    .map_err(|_| "Scale matrix is not positive definite")?;
+
+            let log_precision = Self::multivariate_digamma(self.degrees_of_freedom[i], p)
+                + dimension * two.ln()
+                - log_determinant;
+
+            distances.iter().enumerate().for_each(|(idx, distance)| {
+                log_rho[i][idx] = log_weights[i] + log_precision / two
+                    - dimension / two * ln_two_pi
+                    - (dimension / self.mean_precisions[i] + self.degrees_of_freedom[i] * *distance)
+                        / two
+            });
+        }
+
+        Ok(log_rho)
+    }
+
+    /// Returns the probability of each component for each data point
+    pub fn predict_proba(&self, data: &Matrix<T>) -> Result<Matrix<T>, &'static str> {
+        if self.means.is_empty() {
+            return Err("The model has not been fitted");
+        }
+
+        let mut gammas = self.log_responsibilities(data)?;
+        normalize_log_densities(&mut gammas);
+
+        Ok(gammas)
+    }
+
+    /// Returns the most likely component of each data point
+    pub fn predict(&self, data: &Matrix<T>) -> Result<Vec<usize>, &'static str> {
+        let probabilities = self.predict_proba(data)?;
+
+        Ok((0..probabilities.columns)
+            .map(|col| {
+                (0..probabilities.rows).fold(0, |best, row| {
+                    if probabilities[row][col] > probabilities[best][col] {
+                        row
+                    } else {
+                        best
+                    }
+                })
+            })
+            .collect())
+    }
+
+    /// Computes the responsibility weighted counts, means and covariances
+    fn statistics(&self, data: &Matrix<T>, gammas: &Matrix<T>) -> ComponentStatistics<T> {
+        let p = data.columns;
+        let nk: Vec<T> = (0..self.k)
+            .map(|i| gammas[i].iter().fold(T::zero(), |acc, gamma| acc + *gamma))
+            .collect();
+
+        let mut means: Matrix<T> = Matrix::zeroes(self.k, p);
+        let mut scatters: Vec<Matrix<T>> = Vec::with_capacity(self.k);
+
+        for i in 0..self.k {
+            let mut scatter: Matrix<T> = Matrix::zeroes(p, p);
+
+            // An empty component keeps the prior mean and no scatter
+            if nk[i] <= T::epsilon() {
+                means[i].copy_from_slice(&self.prior_mean);
+                scatters.push(scatter);
+                continue;
+            }
+
+            (0..data.rows).for_each(|row| {
+                (0..p)
+                    .for_each(|col| means[i][col] = means[i][col] + gammas[i][row] * data[row][col])
+            });
+            means[i].iter_mut().for_each(|mean| *mean = *mean / nk[i]);
+
+            (0..data.rows).for_each(|row| {
+                (0..p).for_each(|col1| {
+                    (0..p).for_each(|col2| {
+                        scatter[col1][col2] = scatter[col1][col2]
+                            + gammas[i][row]
+                                * (data[row][col1] - means[i][col1])
+                                * (data[row][col2] - means[i][col2])
+                    })
+                })
+            });
+            scatter.divide_by_scalar(nk[i]);
+            scatters.push(scatter);
+        }
+
+        ComponentStatistics {
+            nk,
+            means,
+            scatters,
+        }
+    }
+
+    /// Updates the variational posterior from the statistics
+    fn m_step(&mut self, statistics: &ComponentStatistics<T>) {
+        let p = self.prior_mean.len();
+        let beta0 = self.mean_precision_prior;
+
+        self.concentrations = statistics
+            .nk
+            .iter()
+            .map(|n| self.concentration_prior + *n)
+            .collect();
+        self.mean_precisions = statistics.nk.iter().map(|n| beta0 + *n).collect();
+        self.degrees_of_freedom = statistics
+            .nk
+            .iter()
+            .map(|n| self.prior_degrees_of_freedom + *n)
+            .collect();
+
+        let mut means: Matrix<T> = Matrix::zeroes(self.k, p);
+        let mut scale_inverses: Vec<Matrix<T>> = Vec::with_capacity(self.k);
+
+        for i in 0..self.k {
+            let n = statistics.nk[i];
+            let shrinkage = beta0 * n / (beta0 + n);
+
+            (0..p).for_each(|col| {
+                means[i][col] = (beta0 * self.prior_mean[col] + n * statistics.means[i][col])
+                    / self.mean_precisions[i]
+            });
+
+            let mut scale_inverse = self.prior_scale_inverse.clone();
+            (0..p).for_each(|col1| {
+                (0..p).for_each(|col2| {
+                    scale_inverse[col1][col2] = scale_inverse[col1][col2]
+                        + n * statistics.scatters[i][col1][col2]
+                        + shrinkage
+                            * (statistics.means[i][col1] - self.prior_mean[col1])
+                            * (statistics.means[i][col2] - self.prior_mean[col2])
+                })
+            });
+            scale_inverses.push(scale_inverse);
+        }
+
+        self.means = means;
+        self.scale_inverses = scale_inverses;
+    }
+
+    /// Computes the evidence lower bound of the current posterior
+    fn elbo(
+        &self,
+        gammas: &Matrix<T>,
+        statistics: &ComponentStatistics<T>,
+    ) -> Result<T, &'static str> {
+        let two: T = T::from(2.0).unwrap();
+        let p = self.prior_mean.len();
+        let dimension: T = T::from(p).unwrap();
+        let ln_two_pi: T = (two * T::from(std::f64::consts::PI).unwrap()).ln();
+        let beta0 = self.mean_precision_prior;
+        let nu0 = self.prior_degrees_of_freedom;
+
+        let quadratic = |vector: &[T], matrix: &Matrix<T>| {
+            (0..p).fold(T::zero(), |acc, col1| {
+                (0..p).fold(acc, |acc, col2| {
+                    acc + vector[col1] * matrix[col1][col2] * vector[col2]
+                })
+            })
+        };
+        let trace_product = |a: &Matrix<T>, b: &Matrix<T>| {
+            (0..p).fold(T::zero(), |acc, col1| {
+                (0..p).fold(acc, |acc, col2| acc + a[col1][col2] * b[col2][col1])
+            })
+        };
+
+        let prior_lower = cholesky_decomposition(&self.prior_scale_inverse)
+            .map_err(|_| "The covariance prior is not positive definite")?;
+        let prior_log_determinant = -cholesky_log_determinant(&prior_lower);
+
+        let log_weights = self.expected_log_weights();
+        let sum_log_weights = log_weights.iter().fold(T::zero(), |acc, w| acc + *w);
+
+        let mut expected_data = T::zero();
+        let mut expected_parameters = T::zero();
+        let mut entropy_parameters = T::zero();
+        let mut sum_log_precisions = T::zero();
+
+        for i in 0..self.k {
+            let lower = cholesky_decomposition(&self.scale_inverses[i])
+                .map_err(|_| "Scale matrix is not positive definite")?;
+            let log_determinant = -cholesky_log_determinant(&lower);
+            let scale = cholesky_inverse(&self.scale_inverses[i])
+                .map_err(|_| "Scale matrix is not positive definite")?;
+
+            let nu = self.degrees_of_freedom[i];
+            let beta = self.mean_precisions[i];
+            let log_precision =
+                Self::multivariate_digamma(nu, p) + dimension * two.ln() + log_determinant;
+            sum_log_precisions = sum_log_precisions + log_precision;
+
+            let to_data: Vec<T> = (0..p)
+                .map(|col| statistics.means[i][col] - self.means[i][col])
+                .collect();
+            let to_prior: Vec<T> = (0..p)
+                .map(|col| self.means[i][col] - self.prior_mean[col])
+                .collect();
+
+            expected_data = expected_data
+                + statistics.nk[i]
+                    * (log_precision
+                        - dimension / beta
+                        - nu * trace_product(&statistics.scatters[i], &scale)
+                        - nu * quadratic(&to_data, &scale)
+                        - dimension * ln_two_pi)
+                    / two;
+
+            expected_parameters = expected_parameters
+                + (dimension * (beta0.ln() - ln_two_pi) + log_precision
+                    - dimension * beta0 / beta
+                    - beta0 * nu * quadratic(&to_prior, &scale))
+                    / two
+                - nu * trace_product(&self.prior_scale_inverse, &scale) / two;
+
+            let wishart_entropy = -Self::log_wishart_normalisation(log_determinant, nu, p)
+                - (nu - dimension - T::one()) / two * log_precision
+                + nu * dimension / two;
+
+            entropy_parameters = entropy_parameters
+                + log_precision / two
+                + dimension / two * (beta.ln() - ln_two_pi)
+                - dimension / two
+                - wishart_entropy;
+        }
+
+        let components: T = T::from(self.k).unwrap();
+        expected_parameters = expected_parameters
+            + components * Self::log_wishart_normalisation(prior_log_determinant, nu0, p)
+            + (nu0 - dimension - T::one()) / two * sum_log_precisions;
+
+        let expected_labels =
+            (0..self.k).fold(T::zero(), |acc, i| acc + statistics.nk[i] * log_weights[i]);
+
+        let expected_weights =
+            Self::log_dirichlet_normalisation(&vec![self.concentration_prior; self.k])
+                + (self.concentration_prior - T::one()) * sum_log_weights;
+
+        let entropy_labels = gammas.content.iter().fold(T::zero(), |acc, gamma| {
+            if *gamma > T::zero() {
+                acc + *gamma * gamma.ln()
+            } else {
+                acc
+            }
+        });
+
+        let entropy_weights = (0..self.k).fold(T::zero(), |acc, i| {
+            acc + (self.concentrations[i] - T::one()) * log_weights[i]
+        }) + Self::log_dirichlet_normalisation(&self.concentrations);
+
+        Ok(
+            expected_data + expected_labels + expected_weights + expected_parameters
+                - entropy_labels
+                - entropy_weights
+                - entropy_parameters,
+        )
+    }
+
+    /// Fits the variational mixture using coordinate ascent on the ELBO
+    ///
+    /// The responsibilities start from Kmeans and the fit stops once the ELBO
+    /// improves by less than the tolerance.
+    pub fn fit(
+        &mut self,
+        data: &Matrix<T>,
+        distance: &dyn distances::Distance<T>,
+    ) -> Result<&'static str, &'static str> {
+        if data.rows < self.k {
+            return Err("Not enough rows to initialise the components");
+        }
+
+        if self.concentration_prior <= T::zero() || self.mean_precision_prior <= T::zero() {
+            return Err("The priors have to be positive");
+        }
+
+        let p = data.columns;
+
+        self.prior_mean = (0..p)
+            .map(|col| {
+                (0..data.rows).fold(T::zero(), |acc, row| acc + data[row][col])
+                    / T::from(data.rows).unwrap()
+            })
+            .collect();
+        self.prior_scale_inverse = match &self.covariance_prior {
+            Some(prior) => prior.clone(),
+            None => covariance(data, &Partition::single(data.rows), 0),
+        };
+        self.prior_degrees_of_freedom = self
+            .degrees_of_freedom_prior
+            .unwrap_or_else(|| T::from(p).unwrap());
+
+        if self.prior_degrees_of_freedom <= T::from(p).unwrap() - T::one() {
+            return Err("The degrees of freedom prior has to be larger than the number of columns minus one");
+        }
+
+        let mut init = kmeans::Kmeans::init(self.k, data, self.seed, distance)?;
+        init.fit(data, None)?;
+
+        let mut gammas: Matrix<T> = init.partition.to_responsibilities();
+
+        self.elbo_trace = Vec::new();
+        self.steps = 0;
+        let mut counter = 0;
+
+        loop {
+            // Update of the parameters
+            let statistics = self.statistics(data, &gammas);
+            self.m_step(&statistics);
+
+            let elbo = self.elbo(&gammas, &statistics)?;
+            let difference = match self.elbo_trace.last() {
+                Some(previous) => (elbo - *previous).abs(),
+                None => T::infinity(),
+            };
+            self.elbo_trace.push(elbo);
+
+            counter += 1;
+
+            if difference < self.tolerance {
+                self.gammas = gammas;
+                self.final_difference = T::to_f32(&difference).unwrap();
+                return Ok("Converged");
+            }
+
+            if counter == self.max_steps {
+                return Err("Did not converge");
+            }
+
+            // Update of the responsibilities
+            gammas = self.log_responsibilities(data)?;
+            normalize_log_densities(&mut gammas);
+            self.steps += 1;
+        }
+    }
+}
@@ -3,6 +3,8 @@ use std::{error::Error, fs::File};
 use crate::calculations::matrix::Matrix;
 
 /// Reads a CSV and parses it into a Matrix<T> struct
+///
+/// Missing cells, either blank or NA, are kept as NaN.
 pub fn read_parse(path: &str) -> Result<(Vec<String>, Matrix<f64>), Box<dyn Error>> {
     let file = File::open(path)?;
 
@@ -20,12 +22,9 @@ pub fn read_parse(path: &str) -> Result<(Vec<String>, Matrix<f64>), Box<dyn Erro
     rdr.records().for_each(|f| {
         match f {
             Ok(record) => {
-                record.iter().for_each(|entry| {
-                    match entry.parse::<f64>() {
-                        Ok(entr) => matrix.content.push(entr), //new_row.push(entr),
-                        Err(_) => panic!("Could not parse {entry:?} to a f64"),
-                    }
-                });
+                record
+                    .iter()
+                    .for_each(|entry| matrix.content.push(parse_entry(entry)));
             }
             Err(_) => panic!("Nothing to read"),
         }
@@ -37,3 +36,74 @@ pub fn read_parse(path: &str) -> Result<(Vec<String>, Matrix<f64>), Box<dyn Erro
 
     Ok((headers, matrix))
 }
+
+/// Iterator over the rows of a CSV, returning them chunk by chunk
+///
+/// Only the current chunk is kept in memory.
+pub struct CsvChunks {
+    records: csv::StringRecordsIntoIter<File>,
+    columns: usize,
+    chunk_size: usize,
+}
+
+impl Iterator for CsvChunks {
+    type Item = Matrix<f64>;
+
+    fn next(&mut self) -> Option<Matrix<f64>> {
+        let mut matrix: Matrix<f64> = Matrix::empty();
+        matrix.columns = self.columns;
+
+        while matrix.rows < self.chunk_size {
+            match self.records.next() {
+                Some(Ok(record)) => {
+                    record
+                        .iter()
+                        .for_each(|entry| matrix.content.push(parse_entry(entry)));
+                    matrix.rows += 1;
+                }
+                Some(Err(_)) => panic!("Nothing to read"),
+                None => break,
+            }
+        }
+
+        if matrix.rows == 0 {
+            None
+        } else {
+            Some(matrix)
+        }
+    }
+}
+
+/// Reads a CSV lazily, chunk_size rows at a time
+pub fn read_chunks(
+    path: &str,
+    chunk_size: usize,
+) -> Result<(Vec<String>, CsvChunks), Box<dyn Error>> {
+    if chunk_size == 0 {
+        return Err("The chunk size has to be positive".into());
+    }
+
+    let file = File::open(path)?;
+
+    let mut rdr = csv::Reader::from_reader(file);
+    let headers: Vec<String> = rdr.headers()?.iter().map(|attr| attr.to_string()).collect();
+
+    let chunks = CsvChunks {
+        records: rdr.into_records(),
+        columns: headers.len(),
+        chunk_size,
+    };
+
+    Ok((headers, chunks))
+}
+
+/// Parses a CSV cell, missing cells (blank or NA) become NaN
+fn parse_entry(entry: &str) -> f64 {
+    match entry.trim() {
+        "" | "NA" => f64::NAN,
+        trimmed => match trimmed.parse::<f64>() {
+            Ok(entr) => entr,
+            Err(_) => panic!("Could not parse {entry:?} to a f64"),
+        },
+    }
+}
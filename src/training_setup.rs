@@ -1,6 +1,21 @@
 use num::Float;
 
+/// Mixture model used to cluster the data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClusteringModel {
+    Gaussian,
+    StudentT {
+        shared_degrees_of_freedom: bool,
+    },
+    FactorAnalyzers {
+        factors: usize,
+        shared_uniquenesses: bool,
+    },
+}
+
 /// Struct containing the training information
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrainingSetup<T> {
     pub number_clusters: usize,
     pub seed: u64,
@@ -9,6 +24,7 @@ pub struct TrainingSetup<T> {
     pub initial_mixtures: Vec<T>,
     pub verbose: bool,
     pub cores: usize,
+    pub model: ClusteringModel,
 }
 
 impl<T: Float> Clone for TrainingSetup<T> {
@@ -21,6 +37,7 @@ impl<T: Float> Clone for TrainingSetup<T> {
             initial_mixtures: self.initial_mixtures.clone(),
             verbose: self.verbose,
             cores: self.cores,
+            model: self.model,
         }
     }
 }
@@ -1,15 +1,39 @@
-use clustvarsel::calculations::stats::slow_covariance;
+use clustvarsel::calculations::partition::Partition;
+use clustvarsel::calculations::stats::{
+    covariance, means_zs, weighted_covariance, weighted_means_zs, WeightKind,
+};
 use clustvarsel::helpers::mean_squared_error;
+use clustvarsel::models::bootstrap::{bootstrap, bootstrap_lrt, BootstrapKind};
 use clustvarsel::models::clustvarsel::CLUSTVARSEL;
-use clustvarsel::models::gmm::GaussianMixtureModel;
+use clustvarsel::models::discriminant::DiscriminantAnalysis;
+use clustvarsel::models::gmm::{
+    CovarianceModel, Degeneracy, DegeneratePolicy, EmAlgorithm, GaussianMixtureModel,
+    Initialization, NoiseComponent,
+};
+use clustvarsel::models::merging::merge_components;
+use clustvarsel::models::mfa::MixtureOfFactorAnalyzers;
+use clustvarsel::models::mixture::MixtureModel;
+use clustvarsel::models::model_selection::mclust_bic;
+use clustvarsel::models::tmm::StudentTMixtureModel;
+use clustvarsel::models::vbgmm::VariationalGaussianMixture;
+use clustvarsel::training_setup::ClusteringModel;
 use clustvarsel::{
-    calculations::{matrix::Matrix, stats::slow_multivariate_gaussian},
+    calculations::{
+        matrix::Matrix,
+        stats::{log_multivariate_gaussian, slow_multivariate_gaussian},
+    },
     models::{
-        distances::{eucleadian_distance, Distance},
-        kmeans::Kmeans,
+        distances::{
+            eucleadian_distance, Cosine, Distance, Euclidean, Mahalanobis, Manhattan, Minkowski,
+            SquaredEuclidean,
+        },
+        kmeans::{EmptyClusterPolicy, Kmeans, KmeansAlgorithm},
+        kmedoids::{Kmedoids, KmedoidsAlgorithm},
     },
     parser,
 };
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Arc;
 
 #[test]
 fn test_kmeans() {
@@ -24,19 +48,358 @@ fn test_kmeans() {
 
     let data: Matrix<f64> = Matrix::from_2d_vector(data_vec);
 
-    let distance: Distance<f64> = eucleadian_distance;
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut ini = Kmeans::init(2, &data, 5, distance).unwrap();
+
+    assert!(ini.fit(&data, None).is_ok());
+
+    // The D² seeding ends in the 4 + 2 split, its inertia of 0.625 is lower than
+    // the 0.667 of the 3 + 3 split the former distance matrix seeding ended in
+    assert_eq!(vec![vec![0.25, 0.25], vec![1.0, 0.25]], ini.centroids);
+    assert!((ini.inertia().unwrap() - 0.625).abs() < 1e-12);
+
+    // With two centroids the greedy variant draws the same first candidate as the
+    // plain Kmeans++ and keeps the best of its draws, so it never starts worse
+    let plain = Kmeans::init(2, &data, 5, distance).unwrap();
+    let mut greedy = Kmeans::init_with_trials(2, &data, None, 5, distance, 3).unwrap();
+    assert!(greedy.score(&data, None).unwrap() >= plain.score(&data, None).unwrap());
+    assert!(greedy.fit(&data, None).is_ok());
+    assert_eq!(greedy.centroids, ini.centroids);
+
+    // Duplicated rows never make a centroid appear twice while distinct rows are left
+    let duplicated: Matrix<f64> = Matrix::from_2d_vector(vec![
+        vec![0.0, 0.0],
+        vec![0.0, 0.0],
+        vec![0.0, 0.0],
+        vec![4.0, 4.0],
+    ]);
+    let seeded = Kmeans::init(2, &duplicated, 3, distance).unwrap();
+    assert_ne!(seeded.centroids[0], seeded.centroids[1]);
+
+    // Rows equal to a centre have no D² weight, they are never drawn again
+    let (repeated, _) = repeated_rows(&two_clusters());
+    (0..20).for_each(|seed| {
+        [1, 3].iter().for_each(|trials| {
+            let seeded =
+                Kmeans::init_with_trials(10, &repeated, None, seed, distance, *trials).unwrap();
+            (0..10).for_each(|a| {
+                (0..a).for_each(|b| assert_ne!(seeded.centroids[a], seeded.centroids[b]));
+            });
+        });
+    });
+}
+
+#[test]
+fn test_accelerated_kmeans() {
+    // Uniform rows from a deterministic generator, Kmeans needs many iterations on them
+    let mut state: u64 = 42;
+    let mut uniform = || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    };
+    let rows: Vec<Vec<f64>> = (0..2000).map(|_| vec![uniform(), uniform()]).collect();
+    let data: Matrix<f64> = Matrix::from_2d_vector(rows);
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut lloyd = Kmeans::init(6, &data, 1, distance).unwrap();
+    assert!(lloyd.fit(&data, None).is_ok());
+
+    for algorithm in [KmeansAlgorithm::Elkan, KmeansAlgorithm::Hamerly] {
+        let mut accelerated = Kmeans::init(6, &data, 1, distance).unwrap();
+        accelerated.algorithm = algorithm;
+        assert!(accelerated.fit(&data, None).is_ok());
+
+        assert_eq!(accelerated.centroids, lloyd.centroids);
+        assert_eq!(accelerated.partition, lloyd.partition);
+        assert!(accelerated.distance_calls * 2 < lloyd.distance_calls);
+    }
+}
+
+#[test]
+fn test_kmeans_convergence_controls() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
 
-    let mut ini = Kmeans::init(2, &data, 5, distance);
+    let mut converged = Kmeans::init(2, &data, 5, distance).unwrap();
+    assert_eq!(converged.fit(&data, None), Ok("Converged"));
 
-    ini.fit(&data);
+    // The inertia never increases with Lloyd
+    let history = &converged.inertia_history;
+    assert!(!history.is_empty());
+    assert!(history.windows(2).all(|pair| pair[1] <= pair[0]));
+    assert_eq!(converged.inertia(), history.last().copied());
 
+    let mut capped = Kmeans::init(2, &data, 5, distance).unwrap();
+    capped.max_iterations = 1;
     assert_eq!(
-        vec![
-            vec![0.16666666666666666, 0.3333333333333333],
-            vec![0.8333333333333334, 0.16666666666666666]
-        ],
-        ini.centroids
+        capped.fit(&data, None),
+        Ok("Reached the maximum number of iterations")
     );
+    assert_eq!(capped.inertia_history.len(), 1);
+
+    let mut tolerant = Kmeans::init(2, &data, 5, distance).unwrap();
+    tolerant.tolerance = f64::INFINITY;
+    assert_eq!(tolerant.fit(&data, None), Ok("Converged"));
+    assert_eq!(tolerant.inertia_history.len(), 1);
+
+    // A centroid far from every row is left empty by the first assignment
+    let far_away = vec![vec![0.0, 0.0], vec![1e6, 1e6]];
+
+    let mut failing = Kmeans::init(2, &data, 5, distance).unwrap();
+    failing.centroids = far_away.clone();
+    failing.empty_cluster_policy = EmptyClusterPolicy::Fail;
+    assert!(failing.fit(&data, None).is_err());
+
+    let mut relocated = Kmeans::init(2, &data, 5, distance).unwrap();
+    relocated.centroids = far_away;
+    assert!(relocated.fit(&data, None).is_ok());
+    assert!(relocated
+        .centroids
+        .iter()
+        .flatten()
+        .all(|coordinate| coordinate.is_finite() && *coordinate < 1e6));
+    assert!(relocated.partition.sizes().iter().all(|size| *size > 0));
+
+    assert!(converged.fit(&data, Some(&[1.0])).is_err());
+}
+
+#[test]
+fn test_distances() {
+    let a = [1.0, 2.0, 3.0];
+    let b = [4.0, 6.0, 3.0];
+
+    let squared: &dyn Distance<f64> = &SquaredEuclidean;
+    assert_eq!(squared.distance(&a, &b).unwrap(), 25.0);
+    assert_eq!(eucleadian_distance(&a, &b).unwrap(), 25.0);
+    assert_eq!(Euclidean.distance(&a, &b).unwrap(), 5.0);
+    assert_eq!(Manhattan.distance(&a, &b).unwrap(), 7.0);
+    assert!(Manhattan.distance(&a, &b[1..]).is_err());
+
+    let minkowski = Minkowski::new(2.0).unwrap();
+    assert!((minkowski.distance(&a, &b).unwrap() - 5.0).abs() < 1e-12);
+    assert!(Minkowski::new(0.5).is_err());
+
+    assert!(Cosine.distance(&[1.0_f64, 0.0], &[2.0, 0.0]).unwrap().abs() < 1e-12);
+    assert!((Cosine.distance(&[1.0_f64, 0.0], &[0.0, 3.0]).unwrap() - 1.0).abs() < 1e-12);
+    assert!(Cosine.distance(&[0.0, 0.0], &[1.0, 0.0]).is_err());
+
+    // Each column is scaled by its standard deviation
+    let covariance = Matrix::from_2d_vector(vec![vec![4.0, 0.0], vec![0.0, 9.0]]);
+    let mahalanobis = Mahalanobis::new(&covariance).unwrap();
+    let d = mahalanobis.distance(&[0.0, 0.0], &[2.0, 3.0]).unwrap();
+    assert!((d - 2.0_f64.sqrt()).abs() < 1e-12);
+    assert!(mahalanobis.distance(&a, &b).is_err());
+    let singular = Matrix::from_2d_vector(vec![vec![1.0, 1.0], vec![1.0, 1.0]]);
+    assert!(Mahalanobis::new(&singular).is_err());
+
+    // Closures are metrics too, Kmeans++ and every iteration go through the chosen one
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let counting = move |x: &[f64], y: &[f64]| {
+        counter.fetch_add(1, AtomicOrdering::Relaxed);
+        Manhattan.distance(x, y)
+    };
+
+    let data = two_clusters();
+    let mut kmeans = Kmeans::init(2, &data, 5, &counting).unwrap();
+    assert!(calls.load(AtomicOrdering::Relaxed) > 0);
+    assert_eq!(kmeans.fit(&data, None), Ok("Converged"));
+
+    (0..data.rows).for_each(|row| {
+        let closest = (0..2)
+            .min_by(|x, y| {
+                let dx = Manhattan
+                    .distance(&data[row], &kmeans.centroids[*x])
+                    .unwrap();
+                let dy = Manhattan
+                    .distance(&data[row], &kmeans.centroids[*y])
+                    .unwrap();
+                dx.partial_cmp(&dy).unwrap()
+            })
+            .unwrap();
+        assert_eq!(kmeans.partition.labels()[row], closest);
+    });
+
+    // Kmeans returns the errors of the distance instead of panicking
+    let mut cosine = Kmeans::init(2, &data, 5, &Cosine).unwrap();
+    assert!(cosine.fit(&data, None).is_ok());
+
+    let null_row = Matrix::from_2d_vector(vec![vec![0.0, 0.0]]);
+    assert!(cosine.predict(&null_row).is_err());
+    assert!(cosine.transform(&null_row).is_err());
+
+    let mut with_null = data.clone();
+    with_null
+        .append_vector(&[0.0, 0.0], 0)
+        .expect("Unable to append row");
+    assert!(Kmeans::init(2, &with_null, 5, &Cosine).is_err());
+    assert!(cosine.fit(&with_null, None).is_err());
+}
+
+#[test]
+fn test_kmedoids() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &Manhattan;
+
+    // Best deviation over every pair of rows
+    let deviation_of = |medoids: &[usize]| {
+        (0..data.rows).fold(0.0, |acc, row| {
+            acc + medoids
+                .iter()
+                .map(|m| distance.distance(&data[row], &data[*m]).unwrap())
+                .fold(f64::INFINITY, f64::min)
+        })
+    };
+    let optimum = (0..data.rows)
+        .flat_map(|a| (a + 1..data.rows).map(move |b| (a, b)))
+        .map(|(a, b)| deviation_of(&[a, b]))
+        .fold(f64::INFINITY, f64::min);
+
+    for algorithm in [KmedoidsAlgorithm::Pam, KmedoidsAlgorithm::FasterPam] {
+        let mut kmedoids = Kmedoids::new(2, 5);
+        kmedoids.algorithm = algorithm;
+        assert_eq!(kmedoids.fit(&data, distance, None), Ok("Converged"));
+
+        assert!((kmedoids.deviation - optimum).abs() < 1e-10);
+        assert!((deviation_of(&kmedoids.medoids) - kmedoids.deviation).abs() < 1e-10);
+        assert_eq!(kmedoids.partition.sizes().iter().sum::<usize>(), data.rows);
+        kmedoids
+            .medoids
+            .iter()
+            .enumerate()
+            .for_each(|(cluster, medoid)| {
+                assert_eq!(kmedoids.partition.labels()[*medoid], cluster);
+            });
+    }
+
+    // Integer weights act as repeated rows
+    let (repeated, weights) = repeated_rows(&data);
+    let mut weighted = Kmedoids::new(2, 5);
+    weighted.algorithm = KmedoidsAlgorithm::Pam;
+    assert!(weighted.fit(&data, distance, Some(&weights)).is_ok());
+    let mut expanded = Kmedoids::new(2, 5);
+    expanded.algorithm = KmedoidsAlgorithm::Pam;
+    assert!(expanded.fit(&repeated, distance, None).is_ok());
+    assert!((weighted.deviation - expanded.deviation).abs() < 1e-10);
+
+    assert!(Kmedoids::new(0, 5).fit(&data, distance, None).is_err());
+    assert!(Kmedoids::new(data.rows + 1, 5)
+        .fit(&data, distance, None)
+        .is_err());
+    assert!(Kmedoids::new(2, 5)
+        .fit(&data, distance, Some(&weights[1..]))
+        .is_err());
+
+    let mut invalid = weights.clone();
+    invalid[0] = -1.0;
+    assert!(Kmedoids::new(2, 5)
+        .fit(&data, distance, Some(&invalid))
+        .is_err());
+    invalid[0] = f64::NAN;
+    assert!(Kmedoids::new(2, 5)
+        .fit(&data, distance, Some(&invalid))
+        .is_err());
+
+    // The medoids can start the components of a GaussianMixtureModel
+    let mut gmm = GaussianMixtureModel::new(2, 5, vec![0.5, 0.5], 200, 1e-5);
+    gmm.initialization = Initialization::Kmedoids;
+    assert!(gmm.fit(&data, distance, None, None).is_ok());
+    assert_eq!(gmm.number_components(), 2);
+}
+
+#[test]
+fn test_kmeans_predict() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut kmeans = Kmeans::init(2, &data, 5, distance).unwrap();
+    assert!(kmeans.fit(&data, None).is_ok());
+
+    // The training rows keep their cluster
+    assert_eq!(kmeans.predict(&data).unwrap(), kmeans.partition.labels());
+
+    let distances = kmeans.transform(&data).unwrap();
+    assert_eq!((distances.rows, distances.columns), (data.rows, 2));
+    assert_eq!(
+        distances[3][1],
+        eucleadian_distance(&data[3], &kmeans.centroids[1]).unwrap()
+    );
+
+    let score = kmeans.score(&data, None).unwrap();
+    assert!((score + kmeans.inertia().unwrap()).abs() < 1e-10);
+
+    let (repeated, weights) = repeated_rows(&data);
+    let weighted = kmeans.score(&data, Some(&weights)).unwrap();
+    assert!((weighted - kmeans.score(&repeated, None).unwrap()).abs() < 1e-10);
+
+    let new_rows = Matrix::from_2d_vector(vec![vec![0.0, 0.0], vec![3.5, 3.5]]);
+    let predicted = kmeans.predict(&new_rows).unwrap();
+    assert_ne!(predicted[0], predicted[1]);
+    assert!(kmeans
+        .predict(&Matrix::from_2d_vector(vec![vec![0.0]]))
+        .is_err());
+    assert!(kmeans.score(&data, Some(&weights[1..])).is_err());
+}
+
+#[test]
+fn test_minibatch_kmeans() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut full = Kmeans::init(2, &data, 5, distance).unwrap();
+    assert!(full.fit(&data, None).is_ok());
+
+    let mut minibatch = Kmeans::init(2, &data, 5, distance).unwrap();
+    assert_eq!(
+        minibatch.fit_minibatch(&data, None, 10, 500, 20, 3),
+        Ok("Converged")
+    );
+
+    // Both end close to the same centroids
+    minibatch
+        .centroids
+        .iter()
+        .zip(&full.centroids)
+        .for_each(|(a, b)| {
+            assert!(mean_squared_error(a, b).unwrap() < 0.1);
+        });
+    assert_eq!(minibatch.partition.k(), 2);
+    assert_eq!(minibatch.partition.len(), data.rows);
+    assert_eq!(minibatch.partition.sizes().iter().sum::<usize>(), data.rows);
+
+    // The batches only depend on the seed
+    let mut again = Kmeans::init(2, &data, 5, distance).unwrap();
+    assert!(again.fit_minibatch(&data, None, 10, 500, 20, 3).is_ok());
+    assert_eq!(again.centroids, minibatch.centroids);
+
+    // Early stopping on the inertia leaves iterations unused
+    assert!(minibatch.inertia_history.len() < 500);
+    assert!(minibatch.distance_calls < (500 * 10 + data.rows) * 2);
+
+    // A centroid far from every row never wins any of them
+    let far_away = vec![vec![0.0, 0.0], vec![1e6, 1e6]];
+
+    let mut failing = Kmeans::init(2, &data, 5, distance).unwrap();
+    failing.centroids = far_away.clone();
+    failing.empty_cluster_policy = EmptyClusterPolicy::Fail;
+    assert!(failing.fit_minibatch(&data, None, 10, 500, 20, 3).is_err());
+
+    let mut relocated = Kmeans::init(2, &data, 5, distance).unwrap();
+    relocated.centroids = far_away;
+    assert!(relocated.fit_minibatch(&data, None, 10, 500, 20, 3).is_ok());
+    assert!(relocated
+        .centroids
+        .iter()
+        .flatten()
+        .all(|coordinate| coordinate.is_finite() && *coordinate < 1e6));
+    assert!(relocated.partition.sizes().iter().all(|size| *size > 0));
+
+    assert!(minibatch
+        .fit_minibatch(&Matrix::empty(), None, 10, 500, 20, 3)
+        .is_err());
 }
 
 #[test]
@@ -88,9 +451,9 @@ fn test_gmm() {
 
     let mut gmm = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 100, 1e-5);
 
-    let distance: Distance<f64> = eucleadian_distance;
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
 
-    match gmm.fit(&data_matrix_form, distance) {
+    match gmm.fit(&data_matrix_form, distance, None, None) {
         Ok(msg) => println!(
             "{}",
             msg.to_owned()
@@ -122,9 +485,7 @@ fn test_multivariate_gaussian() {
 
     let res: Vec<f32> = vec![0.01464371, 0.0182322, 0.01437868, 0.02985463, 0.02354461];
 
-    let zs = vec![1u8, 1u8, 1u8, 1u8, 1u8];
-
-    let mut cov = slow_covariance(&v, &zs);
+    let mut cov = covariance(&v, &Partition::single(v.rows), 0);
     println!("{:?}", cov);
 
     let means = v.mean(0).unwrap();
@@ -142,15 +503,40 @@ fn test_multivariate_gaussian() {
     println!("Gaussian computed");
 }
 
+#[test]
+fn test_log_multivariate_gaussian() {
+    let v: Matrix<f64> = Matrix::from_2d_vector(vec![
+        vec![2.0, 4.0],
+        vec![5.0, 3.0],
+        vec![6.0, 7.0],
+        vec![8.0, 5.0],
+        vec![9.0, 6.0],
+    ]);
+
+    let res: Vec<f64> = vec![0.01464371, 0.0182322, 0.01437868, 0.02985463, 0.02354461];
+
+    let cov = covariance(&v, &Partition::single(5), 0);
+    let means = v.mean(0).unwrap();
+
+    let log_densities = log_multivariate_gaussian(&v, &cov, &means.content).unwrap();
+    let densities: Vec<f64> = log_densities.iter().map(|d| d.exp()).collect();
+
+    let mse = mean_squared_error(&densities, &res).unwrap();
+    assert!(mse < 1e-5, "Error: {}", mse);
+
+    let singular: Matrix<f64> = Matrix::from_2d_vector(vec![vec![1.0, 1.0], vec![1.0, 1.0]]);
+    assert!(log_multivariate_gaussian(&v, &singular, &means.content).is_err());
+}
+
 #[test]
 fn test_1d_gmm() {
     let v: Matrix<f64> = Matrix::from_1d_vector(vec![2.0, 4.0, 5.0, 3.0, 6.0, 7.0, 8.0, 5.0], 8, 1);
 
     let mut gmm = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 100, 1e-5);
 
-    let distance: Distance<f64> = eucleadian_distance;
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
 
-    match gmm.fit(&v, distance) {
+    match gmm.fit(&v, distance, None, None) {
         Ok(msg) => println!(
             "{}",
             msg.to_owned()
@@ -235,3 +621,953 @@ fn test_clustvarsel_cats_and_dogs() {
 
     println!("Final: {:?}", cvs.best_bic);
 }
+
+#[test]
+fn test_gmm_degenerate_component() {
+    let data = Matrix::from_2d_vector(vec![
+        vec![0.1, 0.3],
+        vec![0.4, -0.2],
+        vec![-0.3, 0.1],
+        vec![0.2, 0.5],
+        vec![-0.5, -0.4],
+        vec![0.6, 0.2],
+        vec![-0.1, -0.6],
+        vec![0.3, -0.3],
+        vec![5.0, 5.0],
+    ]);
+
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    // The outlier ends up alone in its cluster so its covariance cannot be computed
+    let mut gmm = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 100, 1e-5);
+    assert!(gmm.fit(&data, distance, None, None).is_err());
+    assert_eq!(gmm.diagnostics[0].kind, Degeneracy::NonFinite);
+    assert_eq!(gmm.diagnostics[0].action, DegeneratePolicy::Fail);
+
+    let mut gmm = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 100, 1e-5);
+    gmm.degenerate_policy = DegeneratePolicy::Drop;
+    assert!(gmm.fit(&data, distance, None, None).is_ok());
+    assert_eq!(gmm.number_components(), 1);
+    assert!(gmm.gammas.content.iter().all(|g| g.is_finite()));
+
+    // Without any component the fit fails and the count stays defined
+    let mut no_component = GaussianMixtureModel::new(0, 4, Vec::new(), 100, 1e-5);
+    assert_eq!(no_component.number_parameters(), 0);
+    assert!(no_component.fit(&data, distance, None, None).is_err());
+}
+
+#[test]
+fn test_mclust_bic() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let selection = mclust_bic(
+        &data,
+        &[1, 2, 3],
+        &CovarianceModel::ALL,
+        4,
+        100,
+        1e-5,
+        distance,
+        3,
+    )
+    .unwrap();
+
+    assert_eq!(selection.table.values.len(), 3);
+    assert_eq!(selection.table.values[0].len(), 6);
+    assert_eq!(selection.top_models.len(), 3);
+
+    let (k, model, bic) = selection.top_models[0];
+    assert_eq!(selection.best_model.number_components(), k);
+    assert_eq!(selection.best_model.covariance_model, model);
+    assert_eq!(selection.best_model.bic(data.rows), bic);
+    assert!(k > 1);
+}
+
+#[test]
+fn test_gmm_noise_component() {
+    let mut data = two_clusters();
+    let outliers = vec![vec![20.0, -15.0], vec![-18.0, 22.0], vec![25.0, 25.0]];
+    for row in &outliers {
+        data.append_vector(row, 0).unwrap();
+    }
+
+    let mut initial_noise = vec![false; data.rows];
+    initial_noise[40..]
+        .iter_mut()
+        .for_each(|noise| *noise = true);
+
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut gmm = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 100, 1e-5);
+    gmm.noise = Some(NoiseComponent::new(initial_noise, &data));
+
+    assert!(gmm.fit(&data, distance, None, None).is_ok());
+
+    let noise = gmm.noise.as_ref().unwrap();
+    assert_eq!(noise.hypervolume, 43.0 * 40.0);
+    assert_eq!(noise.membership.len(), data.rows);
+    assert!(noise.membership[40..].iter().all(|m| *m > 0.99));
+    assert!(noise.membership[..40].iter().all(|m| *m < 0.5));
+    assert_eq!(gmm.gammas.rows, 2);
+}
+
+#[test]
+fn test_student_t_mixture() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut tmm = StudentTMixtureModel::new(2, 4, vec![0.5, 0.5], 200, 1e-5, false);
+    assert!(tmm.fit(&data, distance).is_ok());
+    assert_eq!(tmm.degrees_of_freedom.len(), 2);
+    assert!(tmm.log_likelihood.is_finite());
+
+    let labels = tmm.predict(&data).unwrap();
+    assert_ne!(labels[0], labels[25]);
+
+    let mut shared = StudentTMixtureModel::new(2, 4, vec![0.5, 0.5], 200, 1e-5, true);
+    assert!(shared.fit(&data, distance).is_ok());
+    assert_eq!(shared.degrees_of_freedom[0], shared.degrees_of_freedom[1]);
+
+    // Without any component the fit fails and the count stays defined
+    let mut no_component = StudentTMixtureModel::new(0, 4, Vec::new(), 200, 1e-5, true);
+    assert_eq!(MixtureModel::number_parameters(&no_component), 1);
+    assert!(no_component.fit(&data, distance).is_err());
+}
+
+#[test]
+fn test_clustvarsel_student_t() {
+    let mut cvs = CLUSTVARSEL::new(2, 2, 1e-5, 200, vec![0.5, 0.5], false, 2);
+    cvs.set_model(ClusteringModel::StudentT {
+        shared_degrees_of_freedom: true,
+    });
+
+    assert!(cvs.fit(two_clusters()).is_ok());
+    assert!(!cvs.final_selection.is_empty());
+}
+
+#[test]
+fn test_clustvarsel_criterion_depends_on_model() {
+    let data = heavy_tailed();
+
+    let mut gaussian = CLUSTVARSEL::new(2, 2, 1e-5, 2000, vec![0.5, 0.5], false, 2);
+    assert!(gaussian.fit(data.clone()).is_ok());
+
+    let mut student = CLUSTVARSEL::new(2, 2, 1e-5, 2000, vec![0.5, 0.5], false, 2);
+    student.set_model(ClusteringModel::StudentT {
+        shared_degrees_of_freedom: true,
+    });
+    assert!(student.fit(data.clone()).is_ok());
+
+    assert!((student.best_bic - gaussian.best_bic).abs() > 1e-3);
+
+    // The criterion is the BIC of the t mixture fitted on the selected columns
+    let mut selected: Matrix<f64> = Matrix::empty();
+    student.final_selection.iter().for_each(|col| {
+        selected
+            .append_vector(&data.get_col(*col).content, 1)
+            .expect("Unable to append column");
+    });
+
+    let mut tmm = StudentTMixtureModel::new(2, 2, vec![0.5, 0.5], 2000, 1e-5, true);
+    assert!(MixtureModel::fit(&mut tmm, &selected, &SquaredEuclidean, None, None).is_ok());
+    let parameters = MixtureModel::number_parameters(&tmm) as f64;
+    let bic = -2.0 * tmm.log_likelihood + parameters * (data.rows as f64).ln();
+    assert!((student.best_bic - bic).abs() < 1e-9);
+}
+
+#[test]
+fn test_mixture_of_factor_analyzers() {
+    // Two clusters in 4 dimensions driven by a single latent factor each
+    let clusters = two_clusters();
+    let data = Matrix::from_2d_vector(
+        (0..clusters.rows)
+            .map(|row| {
+                let (a, b) = (clusters[row][0], clusters[row][1]);
+                vec![a, b, a + 0.1 * b, 0.5 * a - b]
+            })
+            .collect(),
+    );
+
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut mfa = MixtureOfFactorAnalyzers::new(2, 2, 4, vec![0.5, 0.5], 500, 1e-6, false);
+    assert!(mfa.fit(&data, distance).is_ok());
+    assert!(mfa.log_likelihood.is_finite());
+    assert_eq!(mfa.loadings[0].rows, 4);
+    assert_eq!(mfa.loadings[0].columns, 2);
+
+    // 2 * 4 means, 1 mixture, 2 * (4 * 2 - 1) loadings and 2 * 4 uniquenesses
+    assert_eq!(MixtureModel::number_parameters(&mfa), 8 + 1 + 14 + 8);
+
+    let labels = mfa.predict(&data).unwrap();
+    assert_ne!(labels[0], labels[25]);
+
+    // Without any factor the fit fails and the count stays defined
+    let mut no_factor = MixtureOfFactorAnalyzers::new(2, 0, 4, vec![0.5, 0.5], 500, 1e-6, false);
+    assert_eq!(MixtureModel::number_parameters(&no_factor), 1);
+    assert!(no_factor.fit(&data, distance).is_err());
+}
+
+#[test]
+fn test_clustvarsel_factor_analyzers() {
+    let mut cvs = CLUSTVARSEL::new(2, 2, 1e-5, 500, vec![0.5, 0.5], false, 2);
+    cvs.set_model(ClusteringModel::FactorAnalyzers {
+        factors: 1,
+        shared_uniquenesses: true,
+    });
+
+    assert!(cvs.fit(two_clusters()).is_ok());
+    assert!(!cvs.final_selection.is_empty());
+}
+
+#[test]
+fn test_gmm_semi_supervised() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    // The first cluster is labelled as component 1 and the second as component 0
+    let mut labels: Vec<Option<usize>> = vec![None; data.rows];
+    labels[0] = Some(1);
+    labels[1] = Some(1);
+    labels[20] = Some(0);
+    labels[21] = Some(0);
+
+    let mut gmm = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 200, 1e-5);
+    assert!(gmm.fit(&data, distance, Some(&labels), None).is_ok());
+    assert!(gmm.log_likelihood.is_finite());
+
+    assert_eq!(gmm.gammas[1][0], 1.0);
+    assert_eq!(gmm.gammas[0][20], 1.0);
+
+    let predicted = gmm.predict(&data).unwrap();
+    assert_eq!(predicted[5], 1);
+    assert_eq!(predicted[25], 0);
+
+    // Labels must match the rows and the number of components
+    assert!(gmm.fit(&data, distance, Some(&labels[1..]), None).is_err());
+    labels[3] = Some(2);
+    assert!(gmm.fit(&data, distance, Some(&labels), None).is_err());
+}
+
+#[test]
+fn test_clustvarsel_semi_supervised() {
+    let mut labels: Vec<Option<usize>> = vec![None; 40];
+    labels[0] = Some(0);
+    labels[20] = Some(1);
+
+    let mut cvs = CLUSTVARSEL::new(2, 2, 1e-5, 200, vec![0.5, 0.5], false, 2);
+    cvs.set_labels(Some(labels));
+
+    assert!(cvs.fit(two_clusters()).is_ok());
+    assert!(!cvs.final_selection.is_empty());
+
+    // Only the GaussianMixtureModel supports known labels
+    let mut labels: Vec<Option<usize>> = vec![None; 40];
+    labels[0] = Some(0);
+
+    let mut cvs = CLUSTVARSEL::new(2, 2, 1e-5, 200, vec![0.5, 0.5], false, 2);
+    cvs.set_model(ClusteringModel::StudentT {
+        shared_degrees_of_freedom: true,
+    });
+    cvs.set_labels(Some(labels.clone()));
+    assert!(cvs.fit(two_clusters()).is_err());
+
+    cvs.set_labels(None);
+    cvs.set_weights(Some(vec![1.0; 40]));
+    assert!(cvs.fit(two_clusters()).is_err());
+
+    // A model failing to fit is reported instead of panicking
+    let mut cvs = CLUSTVARSEL::new(2, 2, 1e-12, 1, vec![0.5, 0.5], false, 2);
+    assert!(cvs.fit(two_clusters()).is_err());
+
+    let mut tmm = StudentTMixtureModel::new(2, 4, vec![0.5, 0.5], 200, 1e-5, false);
+    let data = two_clusters();
+    assert!(MixtureModel::fit(&mut tmm, &data, &SquaredEuclidean, Some(&labels), None).is_err());
+}
+
+#[test]
+fn test_weighted_kmeans() {
+    let data = two_clusters();
+    let (repeated, weights) = repeated_rows(&data);
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut weighted = Kmeans::init(2, &data, 5, distance).unwrap();
+    let mut expanded = Kmeans::init(2, &repeated, 5, distance).unwrap();
+    expanded.centroids = weighted.centroids.clone();
+
+    assert!(weighted.fit(&data, Some(&weights)).is_ok());
+    assert!(expanded.fit(&repeated, None).is_ok());
+
+    weighted
+        .centroids
+        .iter()
+        .zip(&expanded.centroids)
+        .for_each(|(a, b)| {
+            assert!(mean_squared_error(a, b).unwrap() < 1e-10);
+        });
+
+    // A row without weight is never drawn by the seeding, however far it is
+    let outlier = vec![1e3, 1e3];
+    let mut with_outlier = data.clone();
+    with_outlier.append_vector(&outlier, 0).unwrap();
+    let mut outlier_weights = vec![1.0; data.rows];
+    outlier_weights.push(0.0);
+
+    (0..20).for_each(|seed| {
+        [1, 3].iter().for_each(|trials| {
+            [2, 3].iter().for_each(|k| {
+                let seeded = Kmeans::init_with_trials(
+                    *k,
+                    &with_outlier,
+                    Some(&outlier_weights),
+                    seed,
+                    distance,
+                    *trials,
+                )
+                .unwrap();
+                assert!(!seeded.centroids.contains(&outlier));
+            });
+        });
+    });
+
+    assert!(
+        Kmeans::init_with_trials(2, &data, Some(&vec![0.0; data.rows]), 5, distance, 1).is_err()
+    );
+}
+
+#[test]
+fn test_weighted_gmm() {
+    let data = two_clusters();
+    let (repeated, weights) = repeated_rows(&data);
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut weighted = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-10);
+    assert!(weighted.fit(&data, distance, None, Some(&weights)).is_ok());
+
+    let mut expanded = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-10);
+    assert!(expanded.fit(&repeated, distance, None, None).is_ok());
+
+    assert!((weighted.log_likelihood - expanded.log_likelihood).abs() < 1e-6);
+
+    // The component order can differ between both fits
+    let weighted_labels = weighted.predict(&data).unwrap();
+    let expanded_labels = expanded.predict(&data).unwrap();
+    let swapped = weighted_labels[0] != expanded_labels[0];
+    weighted_labels
+        .iter()
+        .zip(&expanded_labels)
+        .for_each(|(a, b)| {
+            assert_eq!(*a != *b, swapped);
+        });
+
+    // Zero weights are allowed, negative ones are not
+    let mut zeroes = weights.clone();
+    zeroes[3] = 0.0;
+    assert!(weighted.fit(&data, distance, None, Some(&zeroes)).is_ok());
+    zeroes[3] = -1.0;
+    assert!(weighted.fit(&data, distance, None, Some(&zeroes)).is_err());
+    assert!(weighted
+        .fit(&data, distance, None, Some(&weights[1..]))
+        .is_err());
+}
+
+#[test]
+fn test_weighted_covariance() {
+    let data = two_clusters();
+    let (repeated, weights) = repeated_rows(&data);
+
+    let single = Partition::single(data.rows);
+    let weighted = weighted_covariance(&data, &single, 0, &weights, WeightKind::Frequency);
+    let expanded = covariance(&repeated, &Partition::single(repeated.rows), 0);
+    assert!(mean_squared_error(&weighted.content, &expanded.content).unwrap() < 1e-10);
+
+    // Survey weights summing to one only matter relative to each other
+    let total: f64 = weights.iter().sum();
+    let normalised: Vec<f64> = weights.iter().map(|w| w / total).collect();
+    let survey = weighted_covariance(&data, &single, 0, &normalised, WeightKind::Reliability);
+    let scaled = weighted_covariance(&data, &single, 0, &weights, WeightKind::Reliability);
+    assert!(mean_squared_error(&survey.content, &scaled.content).unwrap() < 1e-12);
+    assert!((0..data.columns).all(|col| survey[col][col] > 0.0));
+
+    // Equal reliability weights give the unweighted covariance
+    let equal = vec![1.0 / data.rows as f64; data.rows];
+    let survey = weighted_covariance(&data, &single, 0, &equal, WeightKind::Reliability);
+    let unweighted = covariance(&data, &single, 0);
+    assert!(mean_squared_error(&survey.content, &unweighted.content).unwrap() < 1e-12);
+
+    let weighted = weighted_means_zs(&data, &single, 0, &weights).unwrap();
+    let expanded = means_zs(&repeated, &Partition::single(repeated.rows), 0).unwrap();
+    assert!(mean_squared_error(&weighted, &expanded).unwrap() < 1e-10);
+}
+
+#[test]
+fn test_partition() {
+    let v: Matrix<f64> = Matrix::from_2d_vector(vec![
+        vec![1.0, 2.0],
+        vec![3.0, 4.0],
+        vec![5.0, 6.0],
+        vec![7.0, 9.0],
+    ]);
+    let partition = Partition::new(vec![1, 0, 1, 1], 2).unwrap();
+    assert_eq!(partition.sizes(), vec![1, 3]);
+    assert_eq!(partition.members(0).collect::<Vec<usize>>(), vec![1]);
+    assert_eq!(partition.members(1).collect::<Vec<usize>>(), vec![0, 2, 3]);
+    assert_eq!(
+        Partition::single(3).members(0).collect::<Vec<usize>>(),
+        vec![0, 1, 2]
+    );
+    assert!(Partition::new(vec![0, 2], 2).is_err());
+
+    // Only the rows of the cluster are used
+    assert_eq!(means_zs(&v, &partition, 0).unwrap(), vec![3.0, 4.0]);
+    assert_eq!(
+        means_zs(&v, &partition, 1).unwrap(),
+        vec![13.0 / 3.0, 17.0 / 3.0]
+    );
+
+    let one_hot = partition.to_one_hot();
+    assert_eq!(one_hot, vec![vec![0, 1, 0, 0], vec![1, 0, 1, 1]]);
+    assert_eq!(Partition::from_one_hot(&one_hot).unwrap(), partition);
+    assert!(Partition::from_one_hot(&[vec![1, 1], vec![1, 0]]).is_err());
+
+    let responsibilities: Matrix<f64> = partition.to_responsibilities();
+    assert_eq!(
+        responsibilities.content,
+        vec![0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0]
+    );
+    assert_eq!(
+        Partition::from_responsibilities(&responsibilities),
+        partition
+    );
+}
+
+#[test]
+fn test_clustvarsel_weighted() {
+    let data = two_clusters();
+    let (repeated, weights) = repeated_rows(&data);
+
+    let mut weighted = CLUSTVARSEL::new(2, 2, 1e-5, 200, vec![0.5, 0.5], false, 2);
+    weighted.set_weights(Some(weights));
+    assert!(weighted.fit(data).is_ok());
+
+    let mut expanded = CLUSTVARSEL::new(2, 2, 1e-5, 200, vec![0.5, 0.5], false, 2);
+    assert!(expanded.fit(repeated).is_ok());
+
+    assert_eq!(weighted.final_selection, expanded.final_selection);
+    assert!((weighted.best_bic - expanded.best_bic).abs() < 1e-6 * expanded.best_bic.abs());
+}
+
+#[test]
+fn test_gmm_missing_values() {
+    let complete = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    // Without missing entries it matches the usual EM
+    let mut gmm = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-10);
+    assert!(gmm.fit(&complete, distance, None, None).is_ok());
+    let mut missing_gmm = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-10);
+    assert!(missing_gmm.fit_missing(&complete, distance).is_ok());
+    assert!((gmm.log_likelihood - missing_gmm.log_likelihood).abs() < 1e-6);
+
+    // Remove one coordinate of every fifth row and both of row 7
+    let mut data = complete.clone();
+    (0..data.rows)
+        .step_by(5)
+        .for_each(|row| data[row][row % 2] = f64::NAN);
+    data[7][0] = f64::NAN;
+    data[7][1] = f64::NAN;
+
+    let mut gmm = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-8);
+    assert!(gmm.fit_missing(&data, distance).is_ok());
+    assert!(gmm.log_likelihood.is_finite());
+
+    let labels = gmm.predict(&complete).unwrap();
+    assert_ne!(labels[1], labels[21]);
+
+    let imputed = gmm.impute(&data).unwrap();
+    assert!(imputed.content.iter().all(|val| val.is_finite()));
+    assert_eq!(imputed[1], complete[1]);
+
+    // One coordinate known, the other is imputed within its cluster
+    assert!((imputed[20][0] - 3.0).abs() < 1.5);
+    assert!((imputed[5][1] - 0.5).abs() < 1.5);
+
+    let mut empty_column = data.clone();
+    (0..empty_column.rows).for_each(|row| empty_column[row][1] = f64::NAN);
+    assert!(gmm.fit_missing(&empty_column, distance).is_err());
+
+    // The classification and stochastic EM are not available with missing values
+    let mut cem = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-8);
+    cem.algorithm = EmAlgorithm::CEM;
+    assert!(cem.fit_missing(&data, distance).is_err());
+}
+
+#[test]
+fn test_gmm_online() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut batch = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-8);
+    assert!(batch.fit(&data, distance, None, None).is_ok());
+
+    // Alternate both clusters so every chunk sees them, and pass 5 times over the data
+    let chunks: Vec<Matrix<f64>> = (0..5)
+        .flat_map(|_| {
+            (0..5).map(|chunk| {
+                Matrix::from_2d_vector(
+                    (chunk * 4..chunk * 4 + 4)
+                        .flat_map(|row| vec![data[row].to_vec(), data[row + 20].to_vec()])
+                        .collect(),
+                )
+            })
+        })
+        .collect();
+
+    let mut online = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-8);
+    assert!(online.fit_online(chunks.clone(), distance).is_ok());
+    assert_eq!(online.steps, 25);
+
+    // Same clustering as the batch EM
+    let batch_labels = batch.predict(&data).unwrap();
+    let online_labels = online.predict(&data).unwrap();
+    let swapped = batch_labels[0] != online_labels[0];
+    let disagreements = batch_labels
+        .iter()
+        .zip(&online_labels)
+        .filter(|(a, b)| (*a != *b) != swapped)
+        .count();
+    assert!(disagreements <= 1);
+
+    // Incremental updates continue from the current statistics
+    let mut incremental = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-8);
+    chunks
+        .iter()
+        .for_each(|chunk| incremental.partial_fit(chunk, distance).unwrap());
+    assert_eq!(incremental.steps, 25);
+    assert_eq!(incremental.log_likelihood, online.log_likelihood);
+
+    assert!(incremental
+        .partial_fit(&Matrix::from_2d_vector(vec![vec![1.0, 2.0, 3.0]]), distance)
+        .is_err());
+}
+
+#[test]
+fn test_variational_gmm() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    // Three components are allowed, the data only needs two
+    let mut vb = VariationalGaussianMixture::new(3, 1, 1000, 1e-8, 1e-3);
+    assert!(vb.fit(&data, distance).is_ok());
+
+    let weights = vb.effective_weights();
+    assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-10);
+    assert_eq!(vb.number_effective_components(0.05), 2);
+
+    // Coordinate ascent never decreases the ELBO
+    assert!(vb.elbo_trace.len() > 1);
+    vb.elbo_trace.windows(2).for_each(|pair| {
+        assert!(pair[1] >= pair[0] - 1e-8 * pair[0].abs());
+    });
+
+    let labels = vb.predict(&data).unwrap();
+    assert_ne!(labels[0], labels[25]);
+}
+
+#[test]
+fn test_gmm_cem_and_sem() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut em = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-8);
+    assert!(em.fit(&data, distance, None, None).is_ok());
+
+    // Hard assignments, the classification likelihood is below the mixture one
+    let mut cem = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-8);
+    cem.algorithm = EmAlgorithm::CEM;
+    assert!(cem.fit(&data, distance, None, None).is_ok());
+    assert!(cem
+        .gammas
+        .content
+        .iter()
+        .all(|gamma| *gamma == 0.0 || *gamma == 1.0));
+    assert!(cem.log_likelihood.is_finite());
+    assert!(cem.log_likelihood <= em.log_likelihood + 1e-6);
+    assert_ne!(
+        cem.predict(&data).unwrap()[0],
+        cem.predict(&data).unwrap()[25]
+    );
+
+    // Sampled assignments run for max_steps and keep the best step
+    let mut sem = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 50, 1e-8);
+    sem.algorithm = EmAlgorithm::SEM;
+    assert!(sem.fit(&data, distance, None, None).is_ok());
+    assert_eq!(sem.steps, 49);
+    assert!(sem.log_likelihood <= em.log_likelihood + 1e-6);
+    assert!(sem.log_likelihood > em.log_likelihood - 5.0);
+
+    let mut again = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 50, 1e-8);
+    again.algorithm = EmAlgorithm::SEM;
+    assert!(again.fit(&data, distance, None, None).is_ok());
+    assert_eq!(sem.log_likelihood, again.log_likelihood);
+
+    // A component left with too few sampled rows is reseeded, whatever the seed
+    (0..10).for_each(|seed| {
+        [2, 3].iter().for_each(|k| {
+            let mut sampled =
+                GaussianMixtureModel::new(*k, seed, vec![1.0 / *k as f64; *k], 50, 1e-8);
+            sampled.algorithm = EmAlgorithm::SEM;
+            assert!(sampled.fit(&data, distance, None, None).is_ok());
+            assert!(sampled.log_likelihood.is_finite());
+        });
+    });
+}
+
+#[test]
+fn test_discriminant_analysis() {
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    let data = two_clusters();
+    let labels: Vec<usize> = (0..data.rows).map(|row| usize::from(row >= 20)).collect();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut da = DiscriminantAnalysis::new(1, 3, 200, 1e-8);
+    assert!(da.fit(&data, &labels, distance).is_ok());
+    assert_eq!(da.number_classes(), 2);
+
+    let proba = da.predict_proba(&data).unwrap();
+    (0..data.rows).for_each(|col| assert!((proba[0][col] + proba[1][col] - 1.0).abs() < 1e-10));
+    assert!(da.error_rate(&data, &labels).unwrap() < 0.1);
+    assert!(da.cross_validation(&data, &labels, 5, distance).unwrap() < 0.2);
+    assert!(da.fit(&data, &labels[1..], distance).is_err());
+
+    // Class ids without any row are reported instead of failing on an empty class
+    let gap: Vec<usize> = labels.iter().map(|label| 2 * label).collect();
+    assert_eq!(
+        da.fit(&data, &gap, distance),
+        Err("Class labels must be contiguous from 0")
+    );
+
+    // Class 1 only holds the rows of the first fold, so its training rows miss it
+    let mut order: Vec<usize> = (0..data.rows).collect();
+    order.shuffle(&mut rand_chacha::ChaCha8Rng::seed_from_u64(3));
+    let mut three = labels.iter().map(|label| 2 * label).collect::<Vec<usize>>();
+    order
+        .iter()
+        .enumerate()
+        .filter(|(position, _)| position % 5 == 0)
+        .for_each(|(_, row)| three[*row] = 1);
+    assert!(da.cross_validation(&data, &three, 5, distance).is_ok());
+
+    // A column without information about the classes is left out
+    let mut noisy: Matrix<f64> = Matrix::empty();
+    (0..data.rows).for_each(|row| {
+        let mut values = data[row].to_vec();
+        values.push(((row * 7) % 11) as f64);
+        noisy.append_vector(&values, 0).unwrap();
+    });
+
+    let selected = da.select_variables(&noisy, &labels, 5, distance).unwrap();
+    assert!(!selected.contains(&2));
+    assert_eq!(da.variables, Some(selected));
+    assert!(da.selection_errors.windows(2).all(|w| w[1] < w[0]));
+    assert!(da.error_rate(&noisy, &labels).unwrap() < 0.1);
+
+    assert!(da
+        .select_variables(&Matrix::empty(), &[], 5, distance)
+        .is_err());
+}
+
+#[test]
+fn test_merge_components() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let unfitted = GaussianMixtureModel::<f64>::new(3, 4, vec![1.0 / 3.0; 3], 200, 1e-8);
+    assert!(merge_components(&unfitted).is_err());
+
+    let mut gmm = GaussianMixtureModel::new(3, 4, vec![1.0 / 3.0; 3], 200, 1e-8);
+    gmm.covariance_model = CovarianceModel::VII;
+    assert!(gmm.fit(&data, distance, None, None).is_ok());
+
+    let hierarchy = merge_components(&gmm).unwrap();
+    assert_eq!(hierarchy.levels.len(), 3);
+    assert_eq!(hierarchy.levels[0].labels, gmm.predict(&data).unwrap());
+    assert_eq!(hierarchy.levels[0].merged, None);
+
+    // Merging can only lower the entropy
+    hierarchy.levels.windows(2).for_each(|pair| {
+        assert!(pair[1].entropy <= pair[0].entropy + 1e-12);
+    });
+
+    let single = hierarchy.level(1).unwrap();
+    assert_eq!(single.clusters[0].len(), 3);
+    assert!(single.labels.iter().all(|label| *label == 0));
+    assert!(single.entropy.abs() < 1e-12);
+
+    let two = hierarchy.level(2).unwrap();
+    let mut components: Vec<usize> = two.clusters.concat();
+    components.sort_unstable();
+    assert_eq!(components, vec![0, 1, 2]);
+}
+
+#[test]
+fn test_gmm_bootstrap() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let unfitted = GaussianMixtureModel::<f64>::new(2, 4, vec![0.5, 0.5], 200, 1e-8);
+    let kind = BootstrapKind::Nonparametric;
+    assert!(bootstrap(&unfitted, &data, kind, 20, 0.9, distance, 1).is_err());
+
+    let mut gmm = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 200, 1e-8);
+    gmm.covariance_model = CovarianceModel::VVI;
+    assert!(gmm.fit(&data, distance, None, None).is_ok());
+    assert!(bootstrap(&gmm, &data, kind, 1, 0.9, distance, 1).is_err());
+    assert!(bootstrap(&gmm, &data, kind, 20, 1.5, distance, 1).is_err());
+
+    let (samples, components) = gmm.sample(500, 2).unwrap();
+    assert_eq!((samples.rows, samples.columns), (500, 2));
+    assert!(components.iter().all(|c| *c < 2));
+
+    for kind in [
+        BootstrapKind::Nonparametric,
+        BootstrapKind::Parametric,
+        BootstrapKind::WeightedLikelihood,
+    ] {
+        let result = bootstrap(&gmm, &data, kind, 30, 0.9, distance, 7).unwrap();
+        assert_eq!(result.replicates + result.failed, 30);
+        assert_eq!(result.means.standard_errors.rows, 2);
+        assert_eq!(result.mixtures.standard_errors.columns, 1);
+
+        [&result.mixtures, &result.means, &result.variances]
+            .iter()
+            .for_each(|parameter| {
+                parameter.standard_errors.content.iter().for_each(|se| {
+                    assert!(se.is_finite() && *se > 0.0);
+                });
+                parameter
+                    .lower
+                    .content
+                    .iter()
+                    .zip(&parameter.upper.content)
+                    .for_each(|(l, u)| {
+                        assert!(l <= u);
+                    });
+            });
+
+        // The components are matched to the fitted ones, so the intervals surround the fit
+        (0..2).for_each(|i| {
+            (0..2).for_each(|j| {
+                let mean = gmm.means()[i][j];
+                assert!(result.means.lower[i][j] <= mean && mean <= result.means.upper[i][j]);
+            })
+        });
+    }
+}
+
+#[test]
+fn test_bootstrap_lrt() {
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+    let model = CovarianceModel::VII;
+
+    let test = bootstrap_lrt(&data, 3, model, 19, 0.05, 3, 1000, 1e-5, distance).unwrap();
+
+    // One cluster is rejected, the test then stops once G + 1 is no longer needed
+    assert_eq!(test.components[0], 1);
+    assert!(test.p_values[0] <= 0.05);
+    assert!(test.components.len() >= 2);
+    assert!(*test.p_values.last().unwrap() > 0.05 || test.components.len() == 3);
+    test.p_values
+        .iter()
+        .for_each(|p| assert!(*p > 0.0 && *p <= 1.0));
+    test.bootstrap_statistics
+        .iter()
+        .for_each(|b| assert!(!b.is_empty() && b.len() <= 19));
+
+    assert!(bootstrap_lrt(&data, 0, model, 19, 0.05, 3, 1000, 1e-5, distance).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_save_and_load() {
+    use clustvarsel::models::gmm::ComponentDiagnostic;
+    use clustvarsel::models::persistence;
+
+    let data = two_clusters();
+    let distance: &dyn Distance<f64> = &SquaredEuclidean;
+
+    let mut gmm = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 500, 1e-10);
+    assert!(gmm.fit(&data, distance, None, None).is_ok());
+
+    let json = persistence::to_json(&gmm).unwrap();
+    let from_json: GaussianMixtureModel<f64> = persistence::from_json(&json).unwrap();
+    let bytes = persistence::to_binary(&gmm).unwrap();
+    let from_binary: GaussianMixtureModel<f64> = persistence::from_binary(&bytes).unwrap();
+
+    let expected = gmm.predict_proba(&data).unwrap();
+    [from_json, from_binary].iter().for_each(|loaded| {
+        assert_eq!(loaded.log_likelihood, gmm.log_likelihood);
+        let proba = loaded.predict_proba(&data).unwrap();
+        assert!(mean_squared_error(&proba.content, &expected.content).unwrap() < 1e-20);
+    });
+
+    let mut kmeans = Kmeans::init(2, &data, 5, distance).unwrap();
+    assert!(kmeans.fit(&data, None).is_ok());
+    let path = std::env::temp_dir().join("clustvarsel_kmeans.bin");
+    persistence::save_binary(&kmeans, &path).unwrap();
+    let loaded: Kmeans<f64> = persistence::load_binary(&path).unwrap();
+    assert_eq!(loaded.centroids, kmeans.centroids);
+    assert_eq!(loaded.partition, kmeans.partition);
+
+    // The distance is not saved, it has to be set again before predicting
+    let mut manhattan = Kmeans::init(2, &data, 5, &Manhattan).unwrap();
+    assert!(manhattan.fit(&data, None).is_ok());
+    let json = persistence::to_json(&manhattan).unwrap();
+    let mut loaded: Kmeans<f64> = persistence::from_json(&json).unwrap();
+    assert!(loaded.predict(&data).is_err());
+    loaded.set_distance(&Manhattan);
+    assert_eq!(
+        loaded.predict(&data).unwrap(),
+        manhattan.predict(&data).unwrap()
+    );
+
+    // Unfitted models keep their infinite log likelihood and deviation
+    let unfitted = GaussianMixtureModel::<f64>::new(2, 4, vec![0.5, 0.5], 500, 1e-10);
+    let json = persistence::to_json(&unfitted).unwrap();
+    let from_json: GaussianMixtureModel<f64> = persistence::from_json(&json).unwrap();
+    assert_eq!(from_json.log_likelihood, f64::NEG_INFINITY);
+    let bytes = persistence::to_binary(&unfitted).unwrap();
+    let from_binary: GaussianMixtureModel<f64> = persistence::from_binary(&bytes).unwrap();
+    assert_eq!(from_binary.log_likelihood, f64::NEG_INFINITY);
+
+    let kmedoids = Kmedoids::<f64>::new(2, 5);
+    let json = persistence::to_json(&kmedoids).unwrap();
+    let loaded: Kmedoids<f64> = persistence::from_json(&json).unwrap();
+    assert_eq!(loaded.deviation, f64::INFINITY);
+
+    let mut tolerant = Kmeans::init(2, &data, 5, distance).unwrap();
+    tolerant.tolerance = f64::INFINITY;
+    let json = persistence::to_json(&tolerant).unwrap();
+    let loaded: Kmeans<f64> = persistence::from_json(&json).unwrap();
+    assert_eq!(loaded.tolerance, f64::INFINITY);
+
+    // The diagnostics of a degenerate fit are kept, an empty component has no finite weight
+    let mut degenerate = GaussianMixtureModel::new(2, 4, vec![0.5, 0.5], 100, 1e-5);
+    degenerate.degenerate_policy = DegeneratePolicy::Drop;
+    let mut outlier = data.clone();
+    outlier.append_vector(&[50.0, 50.0], 0).unwrap();
+    assert!(degenerate.fit(&outlier, distance, None, None).is_ok());
+    assert!(!degenerate.diagnostics.is_empty());
+    degenerate.diagnostics.push(ComponentDiagnostic {
+        component: 0,
+        step: degenerate.steps,
+        kind: Degeneracy::Empty,
+        nk: f64::NAN,
+        action: DegeneratePolicy::Drop,
+    });
+
+    let json = persistence::to_json(&degenerate).unwrap();
+    let from_json: GaussianMixtureModel<f64> = persistence::from_json(&json).unwrap();
+    let bytes = persistence::to_binary(&degenerate).unwrap();
+    let from_binary: GaussianMixtureModel<f64> = persistence::from_binary(&bytes).unwrap();
+    [from_json, from_binary].iter().for_each(|loaded| {
+        assert_eq!(loaded.diagnostics.len(), degenerate.diagnostics.len());
+        loaded
+            .diagnostics
+            .iter()
+            .zip(&degenerate.diagnostics)
+            .for_each(|(a, b)| {
+                assert_eq!((a.component, a.step, a.kind), (b.component, b.step, b.kind));
+                assert!(a.nk == b.nk || a.nk.is_nan() && b.nk.is_nan());
+            });
+    });
+
+    let mut cvs = CLUSTVARSEL::new(2, 2, 1e-5, 200, vec![0.5, 0.5], false, 2);
+    assert!(cvs.fit(data).is_ok());
+    let path = std::env::temp_dir().join("clustvarsel_selection.json");
+    persistence::save_json(&cvs, &path).unwrap();
+    let loaded: CLUSTVARSEL<f64> = persistence::load_json(&path).unwrap();
+    assert_eq!(loaded.final_selection, cvs.final_selection);
+    assert_eq!(loaded.best_bic, cvs.best_bic);
+
+    // Files written with another format version are rejected
+    let json = persistence::to_json(&gmm).unwrap();
+    let newer = json.replacen("\"format_version\":1", "\"format_version\":2", 1);
+    assert!(persistence::from_json::<GaussianMixtureModel<f64>>(&newer).is_err());
+}
+
+/// Two clusters with a few far away rows
+fn heavy_tailed() -> Matrix<f64> {
+    let mut data = two_clusters();
+    [[-4.0, 2.5], [5.0, -4.5], [8.0, 7.0], [-1.5, 8.5]]
+        .iter()
+        .for_each(|row| {
+            data.append_vector(row, 0).expect("Unable to append row");
+        });
+
+    data
+}
+
+/// Repeats every row 1 to 3 times, returning the repeated rows and the matching weights
+fn repeated_rows(data: &Matrix<f64>) -> (Matrix<f64>, Vec<f64>) {
+    let weights: Vec<f64> = (0..data.rows).map(|row| (1 + row % 3) as f64).collect();
+    let mut repeated: Matrix<f64> = Matrix::empty();
+
+    (0..data.rows).for_each(|row| {
+        (0..1 + row % 3).for_each(|_| {
+            repeated.append_vector(&data[row], 0).unwrap();
+        })
+    });
+
+    (repeated, weights)
+}
+
+/// Two gaussian clusters centered on (0.5, 0.5) and (3, 3)
+fn two_clusters() -> Matrix<f64> {
+    Matrix::from_2d_vector(vec![
+        vec![1.0754941903392263, -0.23128656563065886],
+        vec![0.4574473936178802, 0.9360309725246923],
+        vec![0.32934947297877976, 1.38548750306268],
+        vec![0.5282807898527796, -1.2790598727596125],
+        vec![0.7229444360398654, 0.051849483195611334],
+        vec![-0.9711088688749532, 0.5972057096698321],
+        vec![0.059956970208871474, -0.7683195725500758],
+        vec![0.06507523188571362, 0.5100866099687131],
+        vec![2.621390670414035, 1.1260363315127664],
+        vec![-0.8716491907937076, 0.024930994116899585],
+        vec![0.16012622229024365, -0.5051931551551303],
+        vec![-0.14341732338231827, 1.2396922302991598],
+        vec![0.4608632513583871, -0.2355004832808132],
+        vec![0.8396452500894849, -0.25630691287117346],
+        vec![0.8002203851111599, 2.163947737335655],
+        vec![1.3294368435849129, 0.8596105028558736],
+        vec![1.8505390645568442, -0.1667868269013153],
+        vec![1.0665709281054623, -0.276013437371877],
+        vec![1.1246308563933862, 0.7426135129709652],
+        vec![0.12585120896214103, 0.4982598386969306],
+        vec![2.329942004958563, 3.645549413834696],
+        vec![2.9552284431469484, 2.394856391293376],
+        vec![4.188921565261378, 2.9422653443714863],
+        vec![3.880344690692337, 1.228392170139145],
+        vec![2.4887039201402814, 4.659172805066865],
+        vec![2.111935788965111, 3.4608191188214916],
+        vec![3.170814852951174, 2.9878325954472973],
+        vec![2.5262548875177777, 1.2021936440575964],
+        vec![0.4320207439021613, 1.786987791429834],
+        vec![3.3682528645305965, 4.626355282338605],
+        vec![4.761362496567665, 3.9651351147534957],
+        vec![3.0308191293920927, 3.1865353624620534],
+        vec![4.174158406325235, 3.1611480416571074],
+        vec![2.8527683134876978, 3.830187147596906],
+        vec![4.5023949748381655, 3.677470024796094],
+        vec![3.1927073299205353, 1.9551330147104427],
+        vec![3.0922466979663485, 2.0855491835997255],
+        vec![2.707032712786994, 3.9455880382897823],
+        vec![2.2527686286505206, 1.8939225421290395],
+        vec![1.3136316769676093, 1.7154019350664793],
+    ])
+}
@@ -1,5 +1,6 @@
 use clustvarsel::calculations::linear_algebra::{
-    determinant, lu_decomposition_matrix, matrix_dot_product, slow_inverse_matrix,
+    cholesky_decomposition, cholesky_inverse, determinant, lu_decomposition_matrix,
+    matrix_dot_product, slow_inverse_matrix,
 };
 use clustvarsel::calculations::matrix::Matrix;
 use clustvarsel::helpers::mean_squared_error;
@@ -325,3 +326,35 @@ fn test_matrix_dot() {
     assert_eq!(res.columns, 1);
     assert_eq!(res.rows, 2);
 }
+
+#[test]
+fn test_cholesky_decomposition() {
+    let to_decompose: Matrix<f64> = Matrix::from_2d_vector(vec![
+        vec![4.0, 12.0, -16.0],
+        vec![12.0, 37.0, -43.0],
+        vec![-16.0, -43.0, 98.0],
+    ]);
+
+    let res: Matrix<f64> = Matrix::from_2d_vector(vec![
+        vec![2.0, 0.0, 0.0],
+        vec![6.0, 1.0, 0.0],
+        vec![-8.0, 5.0, 3.0],
+    ]);
+
+    assert_eq!(cholesky_decomposition(&to_decompose), Ok(res));
+
+    let not_positive_definite: Matrix<f64> =
+        Matrix::from_2d_vector(vec![vec![1.0, 2.0], vec![2.0, 1.0]]);
+
+    assert!(cholesky_decomposition(&not_positive_definite).is_err());
+}
+
+#[test]
+fn test_cholesky_inverse() {
+    let to_inverse: Matrix<f64> = Matrix::from_2d_vector(vec![vec![4.0, 2.0], vec![2.0, 3.0]]);
+    let res: Matrix<f64> = Matrix::from_2d_vector(vec![vec![0.375, -0.25], vec![-0.25, 0.5]]);
+
+    let inverse = cholesky_inverse(&to_inverse).unwrap();
+    let err = mean_squared_error(&inverse.content, &res.content).unwrap();
+    assert!(err < 1e-10, "Error: {}", err);
+}
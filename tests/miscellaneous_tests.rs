@@ -1,7 +1,8 @@
 use clustvarsel::calculations::matrix::Matrix;
-use clustvarsel::calculations::stats::slow_covariance;
+use clustvarsel::calculations::partition::Partition;
+use clustvarsel::calculations::stats::{covariance, digamma, ln_gamma};
 use clustvarsel::helpers::mean_squared_error;
-use clustvarsel::parser::read_parse;
+use clustvarsel::parser::{read_chunks, read_parse};
 
 use std::fs::File;
 
@@ -68,9 +69,7 @@ fn test_slow_covariance() {
     let v: Matrix<f32> =
         Matrix::from_1d_vector(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0], 3, 3);
 
-    let zs = vec![1u8, 1u8, 1u8];
-
-    let x = slow_covariance(&v, &zs);
+    let x = covariance(&v, &Partition::single(v.rows), 0);
 
     // Shape expected: columns are the attributes
     assert_eq!(
@@ -86,9 +85,7 @@ fn test_slow_covariance() {
         vec![8.0, 5.0],
         vec![9.0, 6.0],
     ]);
-    let zs = vec![1u8, 1u8, 1u8, 1u8, 1u8];
-
-    let x = slow_covariance(&v, &zs);
+    let x = covariance(&v, &Partition::single(v.rows), 0);
 
     // Shape expected: columns are the attributes
     assert_eq!(
@@ -97,9 +94,9 @@ fn test_slow_covariance() {
     );
     println!("Test 2: Done");
 
-    let zs = vec![0u8, 0u8, 0u8, 1u8, 1u8];
+    let partition = Partition::new(vec![1, 1, 1, 0, 0], 2).unwrap();
 
-    let x = slow_covariance(&v, &zs);
+    let x = covariance(&v, &partition, 0);
 
     // Shape expected: columns are the attributes
     assert_eq!(
@@ -108,9 +105,9 @@ fn test_slow_covariance() {
     );
     println!("Test 3: Done");
 
-    let zs = vec![1u8, 1u8, 1u8, 0u8, 0u8];
+    let partition = Partition::new(vec![0, 0, 0, 1, 1], 2).unwrap();
 
-    let x = slow_covariance(&v, &zs);
+    let x = covariance(&v, &partition, 0);
     let res: Matrix<f32> = Matrix::from_1d_vector(vec![4.33333, 2.16667, 2.16667, 4.33333], 2, 2);
 
     let err = mean_squared_error(&x.content, &res.content).unwrap();
@@ -149,3 +146,45 @@ fn test_read() {
 
     println!("Rows: {:?} Columns {:?}", matrix.rows, matrix.columns);
 }
+
+#[test]
+fn test_special_functions() {
+    assert!((ln_gamma(5.0f64) - 24.0f64.ln()).abs() < 1e-10);
+    assert!((ln_gamma(0.5f64) - std::f64::consts::PI.sqrt().ln()).abs() < 1e-10);
+
+    // digamma(1) is minus the Euler-Mascheroni constant
+    assert!((digamma(1.0f64) + 0.5772156649015329).abs() < 1e-10);
+    assert!((digamma(10.0f64) - 2.251752589066721).abs() < 1e-10);
+}
+
+#[test]
+fn test_read_missing() {
+    let path = std::env::temp_dir().join("clustvarsel_test_read_missing.csv");
+    std::fs::write(&path, "a,b,c\n1.0,,3.0\nNA,5.0, 6.0\n").unwrap();
+
+    let (headers, matrix) = read_parse(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(headers, vec!["a", "b", "c"]);
+    assert_eq!(matrix.rows, 2);
+    assert!(matrix[0][1].is_nan());
+    assert!(matrix[1][0].is_nan());
+    assert_eq!(matrix[0][0], 1.0);
+    assert_eq!(matrix[1][2], 6.0);
+}
+
+#[test]
+fn test_read_chunks() {
+    let (headers, matrix) = read_parse("test.csv").unwrap();
+    let (chunk_headers, chunks) = read_chunks("test.csv", 2).unwrap();
+    assert_eq!(headers, chunk_headers);
+
+    let chunks: Vec<Matrix<f64>> = chunks.collect();
+    assert_eq!(chunks.len(), matrix.rows.div_ceil(2));
+    assert!(chunks.iter().all(|chunk| chunk.rows <= 2));
+
+    let content: Vec<f64> = chunks.into_iter().flat_map(|chunk| chunk.content).collect();
+    assert_eq!(content, matrix.content);
+
+    assert!(read_chunks("test.csv", 0).is_err());
+}